@@ -0,0 +1,35 @@
+use beancount_sort::{find_entries, read_file, sort_entries, ParseOptions, SortOptions};
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::{env, fs};
+
+/// Creates a ledger with roughly `n_lines` lines of unsorted transactions.
+fn synthetic_ledger(n_lines: usize) -> String {
+    let mut ledger = String::from("option \"operating_currency\" \"EUR\"\n");
+    ledger.push_str("2020-01-01 open Assets:Giro EUR\n2020-01-01 open Expenses:Food EUR\n");
+    for i in 0..n_lines / 3 {
+        ledger.push_str(&format!(
+            "2021-{:02}-{:02} * \"Payee {}\" \"Groceries\"\n    Expenses:Food   {}.00 EUR\n    Assets:Giro\n",
+            12 - i % 12,
+            28 - i % 28,
+            i,
+            i % 100
+        ));
+    }
+    ledger
+}
+
+fn bench_sort(c: &mut Criterion) {
+    let path = env::temp_dir().join("beancount-sort_bench.beancount");
+    fs::write(&path, synthetic_ledger(10_000)).unwrap();
+    c.bench_function("find and sort entries of 10 000 lines", |b| {
+        b.iter(|| {
+            let ledger_file =
+                find_entries(read_file(&path).unwrap(), &ParseOptions::default()).unwrap();
+            sort_entries(ledger_file.entries, &SortOptions::default()).unwrap()
+        })
+    });
+    fs::remove_file(&path).unwrap();
+}
+
+criterion_group!(benches, bench_sort);
+criterion_main!(benches);
@@ -0,0 +1,5428 @@
+//! Parsing and sorting of beancount files.
+//!
+//! A file is read with [read_file], split into [Entry]s by [find_entries],
+//! grouped into sections and sorted by date by [sort_entries] and finally
+//! written back with [LedgerFile::write_ledger_file].
+use anyhow::{anyhow, Context, Result};
+use chrono::{naive::NaiveDate, DateTime, Datelike, Utc};
+use encoding_rs::Encoding;
+use lazy_static::lazy_static;
+use log::debug;
+use regex::Regex;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    ffi::OsStr,
+    fmt::{self, Display},
+    fs::{remove_file, OpenOptions},
+    io::{self, prelude::*, BufRead, BufReader},
+    mem,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The sections of a sorted file in the order they are written.
+/// The Custom, Notes and Queries sections are only written if [SortOptions::custom_section],
+/// [SortOptions::notes_section] or [SortOptions::queries_section] is set.
+pub const SECTIONS: [&str; 15] = [
+    "Header",
+    "Options",
+    "Plugins",
+    "Includes",
+    "Accounts",
+    "Commodities",
+    "Other Entries",
+    "Custom",
+    "Notes",
+    "Queries",
+    "Documents",
+    "Prices",
+    "Balances",
+    "Events",
+    "Transactions",
+];
+pub const NDECO: usize = 4; // number of DECO to use at section headings
+pub const DECO: &str = "€";
+
+/// The decoration of the section headings written by [sort_entries], `€` four times by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionDeco {
+    pub deco: char,
+    /// Number of deco characters in front of and behind the section name
+    pub count: usize,
+    /// Width of the heading without the `;`. Headings of shorter section names are filled up
+    /// with deco characters so that all headings line up. 0 fits every heading to its name.
+    pub width: usize,
+}
+
+impl Default for SectionDeco {
+    fn default() -> Self {
+        SectionDeco {
+            deco: DECO.chars().next().unwrap(),
+            count: NDECO,
+            width: 0,
+        }
+    }
+}
+
+impl SectionDeco {
+    /// Returns the heading of a section like:
+    /// ;€€€€€€€€€€€€€€€\n;€€€€Options€€€€\n;€€€€€€€€€€€€€€€
+    pub fn heading(&self, section: &str) -> String {
+        let width = self.width.max(2 * self.count + section.chars().count());
+        let deco = |n: usize| self.deco.to_string().repeat(n);
+        format!(
+            ";{line}\n;{front}{section}{back}\n;{line}",
+            line = deco(width),
+            front = deco(self.count),
+            section = section,
+            back = deco(width - self.count - section.chars().count())
+        )
+    }
+
+    /// Returns true if `line` is the line with the section name of a heading written with this
+    /// decoration, e.g. `;==Prices==`. A comment like `;== TODO` isn't a heading.
+    pub fn is_heading(&self, line: &str) -> bool {
+        banner_deco(line) == Some(self.deco)
+            && line[1..].starts_with(&self.deco.to_string().repeat(self.count))
+    }
+}
+
+/// Returns true if `deco` can decorate section headings. Letters, spaces and quotes can't,
+/// a comment like `;"Notes"` would look like a heading otherwise.
+pub fn is_deco_char(deco: char) -> bool {
+    !(deco.is_alphanumeric() || deco.is_whitespace() || matches!(deco, ';' | '"' | '\''))
+}
+
+/// Returns the decoration character if `line` is the middle line of a section heading
+/// like `;====Prices====`, whatever decoration it was written with.
+/// It is only a heading if the rule lines above and below it are there as well, see [find_entries].
+fn banner_deco(line: &str) -> Option<char> {
+    let rest = line.strip_prefix(';')?;
+    let deco = rest.chars().next()?;
+    if !is_deco_char(deco) {
+        return None;
+    }
+    let name = rest.trim_start_matches(deco);
+    let name = name.strip_suffix(deco)?.trim_end_matches(deco);
+    SECTIONS.contains(&name).then_some(deco)
+}
+
+/// Returns true if `line` is a line like `;==========` above or below the name of a section heading.
+fn is_banner_rule(line: &str, deco: char) -> bool {
+    line.strip_prefix(';')
+        .is_some_and(|rest| rest.chars().count() >= 2 && rest.chars().all(|c| c == deco))
+}
+
+/// Returns true if the last line of the last entry is the rule above the name of a section heading.
+/// Skipped lines in the Header aren't part of a heading.
+fn ends_with_banner_rule(entries: &[Entry], deco: char) -> bool {
+    entries.last().is_some_and(|e| {
+        e.entry_type != EntryType::Header
+            && e.content
+                .lines()
+                .last()
+                .is_some_and(|line| is_banner_rule(line, deco))
+    })
+}
+
+/// The UTF-8 byte order mark that some Windows tools write at the start of a file.
+pub const BOM: char = '\u{feff}';
+
+/// The line ending of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LineEnding {
+    /// Unix line endings (\n)
+    #[default]
+    Lf,
+    /// Windows line endings (\r\n)
+    Crlf,
+}
+
+impl FromStr for LineEnding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::Crlf),
+            _ => Err(anyhow!("Unknown line ending '{}'. Use lf or crlf.", s)),
+        }
+    }
+}
+
+/// Where [LedgerFile::format_ledger_file] leaves empty lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum BlankLines {
+    /// No empty lines at all
+    #[default]
+    None,
+    /// One empty line below every section heading and above the next one
+    Sections,
+    /// One empty line after every entry
+    Entries,
+}
+
+impl FromStr for BlankLines {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(BlankLines::None),
+            "sections" => Ok(BlankLines::Sections),
+            "entries" => Ok(BlankLines::Entries),
+            _ => Err(anyhow!(
+                "Unknown value '{}'. Use none, sections or entries.",
+                s
+            )),
+        }
+    }
+}
+
+/// Path that [read_file] reads from stdin instead of a file, e.g. `--file -`
+pub const STDIN_PATH: &str = "-";
+
+/// Name of stdin in messages and in [LedgerFile::path]
+pub const STDIN_NAME: &str = "<stdin>";
+
+/// Path that [open_output] writes to stdout instead of a file, e.g. `--out -`
+pub const STDOUT_PATH: &str = "-";
+
+/// Opens the file at `path` for writing, an existing file is replaced.
+/// The path [STDOUT_PATH] writes to stdout.
+pub fn open_output(path: &Path) -> Result<Box<dyn Write>> {
+    if path == Path::new(STDOUT_PATH) {
+        return Ok(Box::new(io::stdout()));
+    }
+    if path.exists() {
+        remove_file(path)?;
+    };
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(format!("unable to open '{}'", path.display()))?;
+    Ok(Box::new(file))
+}
+
+/// Passes a temporary file next to `path` to `write` and renames it over `path` once it was
+/// written and synced completely, see [temp_path]. If writing fails the file at `path`
+/// stays untouched and the temporary file is removed.
+/// A file that is replaced keeps its permissions, e.g. a ledger that only its owner can read.
+pub fn write_atomically<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(Box<dyn Write>) -> Result<()>,
+{
+    let tmp = temp_path(path);
+    let file = std::fs::File::create(&tmp)
+        .with_context(|| format!("unable to create '{}'", tmp.display()))?;
+    let permissions = match std::fs::metadata(path) {
+        Ok(metadata) => file
+            .set_permissions(metadata.permissions())
+            .with_context(|| format!("unable to set the permissions of '{}'", tmp.display())),
+        Err(_) => Ok(()),
+    };
+    let written = permissions
+        .and_then(|_| file.try_clone().map_err(anyhow::Error::from))
+        .and_then(|out| write(Box::new(out)))
+        .and_then(|_| {
+            file.sync_all()
+                .with_context(|| format!("unable to write '{}'", tmp.display()))
+        });
+    if let Err(e) = written {
+        let _ = remove_file(&tmp);
+        return Err(e);
+    }
+    if let Err(e) = std::fs::rename(&tmp, path) {
+        let _ = remove_file(&tmp);
+        return Err(anyhow!(
+            "unable to replace '{}' by '{}': {}",
+            path.display(),
+            tmp.display(),
+            e
+        ));
+    }
+    Ok(())
+}
+
+/// Where the content of a [LedgerFile] is read from.
+#[derive(Debug)]
+pub enum Input {
+    File(std::fs::File),
+    Stdin(io::Stdin),
+    /// The content of a file that was transcoded to UTF-8, see [ParseOptions::encoding]
+    Decoded(io::Cursor<Vec<u8>>),
+}
+
+impl Input {
+    /// Returns an input without content, e.g. for a [LedgerFile] whose entries are already known.
+    pub fn empty() -> Input {
+        Input::Decoded(io::Cursor::new(Vec::new()))
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::File(file) => file.read(buf),
+            Input::Stdin(stdin) => stdin.read(buf),
+            Input::Decoded(content) => content.read(buf),
+        }
+    }
+}
+
+/// The main Object that holds all information about a ledger file.
+/// Is returned by the function [read_file]
+/// With the `serde` feature it can be serialized, e.g. to cache it. The [Input] is left out,
+/// its content has already been read into the entries by [find_entries].
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LedgerFile {
+    /// Path of the file, [STDIN_NAME] if it is read from stdin
+    pub path: PathBuf,
+    #[cfg_attr(feature = "serde", serde(skip, default = "Input::empty"))]
+    pub file: Input,
+    pub entries: Vec<Entry>,
+    /// True if the file starts with a UTF-8 byte order mark. It is written again by [LedgerFile::format_ledger_file]
+    pub bom: bool,
+    /// The line ending that is used by most lines of the file and by [LedgerFile::format_ledger_file]
+    pub line_ending: LineEnding,
+    /// The encoding the file was read with if it isn't UTF-8. The sorted file is written with it as well.
+    #[cfg_attr(feature = "serde", serde(with = "encoding_name"))]
+    pub encoding: Option<&'static Encoding>,
+}
+
+/// Serializes an encoding by its name, see [parse_encoding]
+#[cfg(feature = "serde")]
+mod encoding_name {
+    use encoding_rs::Encoding;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        encoding: &Option<&'static Encoding>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match encoding {
+            Some(encoding) => serializer.serialize_some(encoding.name()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<&'static Encoding>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(name) => super::parse_encoding(&name).map_err(D::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+impl LedgerFile {
+    /// Writes the sorted file to `out`, e.g. a file or stdout returned by [open_output]
+    pub fn write_ledger_file(self, mut out: Box<dyn Write>, blank_lines: BlankLines) -> Result<()> {
+        write_encoded(
+            &mut out,
+            &self.format_ledger_file(blank_lines),
+            self.encoding,
+        )
+    }
+
+    /// Writes the Transactions and Prices of every year to `dir/<year>.beancount` and everything else to `out`.
+    /// `out` gets an include directive for every year file in chronological order.
+    /// The includes use `dir` as given, so a relative `dir` has to be relative to the main file.
+    /// Undated entries stay in the main file. Returns the paths of the year files.
+    pub fn write_split_by_year(
+        self,
+        mut out: Box<dyn Write>,
+        dir: &Path,
+        blank_lines: BlankLines,
+    ) -> Result<Vec<PathBuf>> {
+        let mut main_entries: Vec<Entry> = Vec::new();
+        let mut years: BTreeMap<i32, Vec<Entry>> = BTreeMap::new();
+        let mut heading: Option<Entry> = None;
+        let mut moved_headings: Vec<String> = Vec::new();
+        let mut year = None;
+        for entry in self.entries {
+            if entry.entry_type == EntryType::Section && entry.date.is_none() {
+                heading = Some(entry.clone());
+                year = None;
+                main_entries.push(entry);
+                continue;
+            }
+            if entry.entry_type != EntryType::Comment {
+                // month headings are dated and go to the file of their year
+                let in_year_file = matches!(
+                    entry.entry_type.section_variant(),
+                    EntryType::Transaction | EntryType::Price | EntryType::Section
+                );
+                year = entry.date.filter(|_| in_year_file).map(|d| d.year());
+            }
+            match year {
+                Some(year) => {
+                    let year_entries = years.entry(year).or_default();
+                    // repeat the section heading in every year file
+                    if let Some(heading) = &heading {
+                        if !year_entries.iter().any(|e| e.content == heading.content) {
+                            year_entries.push(heading.clone());
+                            moved_headings.push(heading.content.clone());
+                        }
+                    }
+                    year_entries.push(entry);
+                }
+                None => main_entries.push(entry),
+            }
+        }
+        // drop the headings of the sections that were moved to the year files completely
+        let is_heading = |e: &Entry| e.entry_type == EntryType::Section && e.date.is_none();
+        let mut main_entries: Vec<Entry> = main_entries
+            .iter()
+            .enumerate()
+            .filter(|(i, e)| {
+                !(moved_headings.contains(&e.content)
+                    && main_entries.get(i + 1).is_none_or(is_heading))
+            })
+            .map(|(_, e)| e.clone())
+            .collect();
+        let mut paths = Vec::new();
+        for (year, entries) in years {
+            let path = dir.join(format!("{}.beancount", year));
+            let content = format_entries(&entries, blank_lines, &self.line_ending);
+            let encoding = self.encoding;
+            write_atomically(&path, |mut year_out| {
+                write_encoded(&mut year_out, &content, encoding)
+            })
+            .with_context(|| format!("unable to write '{}'", path.display()))?;
+            main_entries.push(Entry {
+                content: format!("include \"{}\"", path.display()),
+                date: None,
+                entry_type: EntryType::Include,
+                secondary_key: None,
+                line_number: 0,
+            });
+            paths.push(path);
+        }
+        let mut output = String::new();
+        if self.bom {
+            output.push(BOM);
+        }
+        output.push_str(&format_entries(
+            &main_entries,
+            blank_lines,
+            &self.line_ending,
+        ));
+        write_encoded(&mut out, &output, self.encoding)?;
+        Ok(paths)
+    }
+
+    /// Returns the content of the file as it would be written by [LedgerFile::write_ledger_file]
+    pub fn format_ledger_file(&self, blank_lines: BlankLines) -> String {
+        let mut output = String::new();
+        if self.bom {
+            output.push(BOM);
+        }
+        output.push_str(&format_entries(
+            &self.entries,
+            blank_lines,
+            &self.line_ending,
+        ));
+        output
+    }
+
+    /// Returns the entries as a JSON array of objects with `date`, `entry_type` and `content`.
+    /// The section headings are left out.
+    #[cfg(feature = "serde")]
+    pub fn format_json(&self) -> Result<String> {
+        let entries: Vec<JsonEntry> = self
+            .entries
+            .iter()
+            .filter(|e| e.entry_type != EntryType::Section)
+            .map(|e| JsonEntry {
+                content: &e.content,
+                date: e.date,
+                entry_type: &e.entry_type,
+            })
+            .collect();
+        Ok(serde_json::to_string_pretty(&entries)? + "\n")
+    }
+}
+
+/// The fields of an [Entry] that are written by [LedgerFile::format_json]
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    content: &'a str,
+    #[serde(with = "date_format")]
+    date: Option<NaiveDate>,
+    entry_type: &'a EntryType,
+}
+
+/// Writes `content` to `out`, transcoded to `encoding` if it isn't UTF-8.
+fn write_encoded(
+    out: &mut Box<dyn Write>,
+    content: &str,
+    encoding: Option<&'static Encoding>,
+) -> Result<()> {
+    let bytes = match encoding {
+        Some(encoding) => {
+            let (bytes, _, unmappable) = encoding.encode(content);
+            if unmappable {
+                return Err(anyhow!(
+                    "The sorted file contains characters that can't be written as {}",
+                    encoding.name()
+                ));
+            }
+            bytes
+        }
+        None => content.as_bytes().into(),
+    };
+    if let Err(e) = out.write_all(&bytes).and_then(|_| out.flush()) {
+        return Err(anyhow!("Couldnt write to file: {}", e));
+    };
+    Ok(())
+}
+
+/// Joins the content of the entries with the given line ending and empty lines.
+fn format_entries(entries: &[Entry], blank_lines: BlankLines, line_ending: &LineEnding) -> String {
+    let mut output = String::new();
+    let is_heading = |e: &Entry| e.entry_type == EntryType::Section;
+    for (i, entry) in entries.iter().enumerate() {
+        output.push_str(&entry.content);
+        output.push('\n');
+        let blank_line = match blank_lines {
+            BlankLines::None => false,
+            BlankLines::Sections => is_heading(entry) || entries.get(i + 1).is_some_and(is_heading),
+            BlankLines::Entries => true,
+        };
+        if blank_line {
+            output.push('\n');
+        };
+    }
+    // the file ends with exactly one newline
+    while output.ends_with("\n\n") {
+        output.pop();
+    }
+    match line_ending {
+        LineEnding::Lf => output,
+        LineEnding::Crlf => output.replace('\n', "\r\n"),
+    }
+}
+
+/// Returns a unified diff between the `original` and the `sorted` content of the file at `path`.
+pub fn unified_diff(original: &str, sorted: &str, path: &Path) -> String {
+    format_diff(original, sorted, path, DiffFormat::Unified)
+}
+
+/// How [format_diff] writes the diff between the original and the sorted file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum DiffFormat {
+    /// A unified diff with the path of the file in both headers
+    #[default]
+    Unified,
+    /// A unified diff with `a/` and `b/` in front of the paths like git writes it.
+    /// It can be applied with `patch -p1` or `git apply`.
+    Patch,
+}
+
+impl FromStr for DiffFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "unified" => Ok(DiffFormat::Unified),
+            "patch" => Ok(DiffFormat::Patch),
+            _ => Err(anyhow!("Unknown value '{}'. Use unified or patch.", s)),
+        }
+    }
+}
+
+/// Returns a diff in the given format between the `original` and the `sorted` content of the file at `path`.
+/// It is empty if the content didn't change.
+pub fn format_diff(original: &str, sorted: &str, path: &Path, format: DiffFormat) -> String {
+    let path = path.display().to_string();
+    let (old, new) = match format {
+        DiffFormat::Unified => (path.clone(), path),
+        DiffFormat::Patch => {
+            let path = path.trim_start_matches("./");
+            (format!("a/{}", path), format!("b/{}", path))
+        }
+    };
+    TextDiff::from_lines(original, sorted)
+        .unified_diff()
+        .header(&old, &new)
+        .to_string()
+}
+
+/// The Entry type holds one entry in a beancount file.
+/// With the `serde` feature all fields are serialized, so entries that are read back are sorted
+/// like the original ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Entry {
+    pub content: String,
+    /// None for entries without a date like options, plugins and comments.
+    /// They are sorted in front of the dated entries of their section, behind them with [SortOptions::descending].
+    #[cfg_attr(feature = "serde", serde(with = "date_format"))]
+    pub date: Option<NaiveDate>,
+    pub entry_type: EntryType,
+    /// Used to order entries with the same date, see [construct_dated_entry]
+    pub secondary_key: Option<String>,
+    /// Line of the source file on which the entry starts, 0 for entries that are not read from a file
+    pub line_number: usize,
+}
+
+/// Serializes a date like beancount writes it, e.g. `2023-01-31`
+#[cfg(feature = "serde")]
+mod date_format {
+    use chrono::NaiveDate;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    const FORMAT: &str = "%Y-%m-%d";
+
+    pub fn serialize<S: Serializer>(
+        date: &Option<NaiveDate>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match date {
+            Some(date) => serializer.serialize_some(&date.format(FORMAT).to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<NaiveDate>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(date) => NaiveDate::parse_from_str(&date, FORMAT)
+                .map(Some)
+                .map_err(D::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Entry {
+    /// Returns the line of the source file on which the entry ends.
+    pub fn last_line_number(&self) -> usize {
+        self.line_number + self.content.lines().count().saturating_sub(1)
+    }
+}
+
+/// Formats the entry as it is written to the sorted file.
+impl Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.content)
+    }
+}
+
+/// All possible types of entries in a beancount file. Used by [Entry]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EntryType {
+    Plugin,
+    Include,
+    Account,
+    ClosedAccount,
+    Option,
+    Commodity,
+    OtherEntry,
+    Query,
+    Custom,
+    Note,
+    Document,
+    Price,
+    Pad,
+    Balance,
+    Event,
+    Transaction,
+    Block,
+    Indented,
+    Section,
+    Header,
+    Comment,
+}
+
+impl EntryType {
+    /// Returns the [EntryType] of the section that entries of this type are sorted into.
+    /// Closed accounts are kept together with the opened ones in the Accounts section
+    /// and pads are kept together with the balance assertions they pad.
+    /// Blocks of pushtag/poptag and pushmeta/popmeta are sorted as a whole into the Transactions section.
+    /// Custom entries, notes and queries are sorted into the Other Entries section unless [SortOptions::custom_section],
+    /// [SortOptions::notes_section] or [SortOptions::queries_section] is set.
+    pub fn section_variant(&self) -> EntryType {
+        match self {
+            EntryType::ClosedAccount => EntryType::Account,
+            EntryType::Pad => EntryType::Balance,
+            EntryType::Block => EntryType::Transaction,
+            EntryType::Custom => EntryType::OtherEntry,
+            EntryType::Note => EntryType::OtherEntry,
+            EntryType::Query => EntryType::OtherEntry,
+            entry_type => entry_type.clone(),
+        }
+    }
+
+    /// Returns the beancount keyword of the directive or a lowercase name for the types
+    /// that have none, like comments and blocks.
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            EntryType::Plugin => "plugin",
+            EntryType::Include => "include",
+            EntryType::Account => "open",
+            EntryType::ClosedAccount => "close",
+            EntryType::Option => "option",
+            EntryType::Commodity => "commodity",
+            EntryType::OtherEntry => "other entry",
+            EntryType::Query => "query",
+            EntryType::Custom => "custom",
+            EntryType::Note => "note",
+            EntryType::Document => "document",
+            EntryType::Price => "price",
+            EntryType::Pad => "pad",
+            EntryType::Balance => "balance",
+            EntryType::Event => "event",
+            EntryType::Transaction => "txn",
+            EntryType::Block => "block",
+            EntryType::Indented => "indented line",
+            EntryType::Section => "section",
+            EntryType::Header => "header",
+            EntryType::Comment => "comment",
+        }
+    }
+
+    /// Returns true if indented lines (postings or metadata) can follow an entry of this type.
+    /// Beancount allows metadata under every dated directive.
+    pub fn takes_indented_lines(&self) -> bool {
+        !matches!(
+            self,
+            EntryType::Plugin
+                | EntryType::Include
+                | EntryType::Option
+                | EntryType::Block
+                | EntryType::Indented
+                | EntryType::Section
+                | EntryType::Header
+                | EntryType::Comment
+        )
+    }
+}
+
+/// Formats the type with its [EntryType::keyword]
+impl Display for EntryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.keyword())
+    }
+}
+
+/// Returns the [EntryType] that belongs to a section name of [SECTIONS].
+impl TryFrom<&str> for EntryType {
+    type Error = anyhow::Error;
+
+    fn try_from(section: &str) -> Result<Self> {
+        let entry_type = match section {
+            "Accounts" => EntryType::Account,
+            "Options" => EntryType::Option,
+            "Plugins" => EntryType::Plugin,
+            "Includes" => EntryType::Include,
+            "Commodities" => EntryType::Commodity,
+            "Other Entries" => EntryType::OtherEntry,
+            "Prices" => EntryType::Price,
+            "Balances" => EntryType::Balance,
+            "Events" => EntryType::Event,
+            "Documents" => EntryType::Document,
+            "Custom" => EntryType::Custom,
+            "Notes" => EntryType::Note,
+            "Queries" => EntryType::Query,
+            "Transactions" => EntryType::Transaction,
+            "Header" => EntryType::Header,
+            _ => {
+                return Err(anyhow!(
+                    "Unknown section \"{}\". Known sections are: {}",
+                    section,
+                    SECTIONS.join(", ")
+                ))
+            }
+        };
+        Ok(entry_type)
+    }
+}
+
+/// The type of a line. Returned by [get_line_type]
+#[derive(Debug, Clone)]
+pub enum Line {
+    Date(NaiveDate),
+    Option,
+    Plugin,
+    Include,
+    PushTag(String),
+    PopTag(String),
+    PushMeta(String),
+    PopMeta(String),
+    Comment,
+    Indent,
+    Empty,
+    Section,
+    OrgHeading,
+    /// A line that [get_line_type] couldn't classify. Only used by [find_entries] with [ParseOptions::lenient]
+    Unclassified,
+}
+
+/// Reads a file at a given Path. Returns a Result with either a [LedgerFile] or an Error
+/// The path [STDIN_PATH] reads from stdin.
+pub fn read_file(path: &Path) -> Result<LedgerFile> {
+    if path == Path::new(STDIN_PATH) {
+        return Ok(LedgerFile {
+            path: PathBuf::from(STDIN_NAME),
+            file: Input::Stdin(io::stdin()),
+            entries: Vec::new(),
+            bom: false,
+            line_ending: LineEnding::default(),
+            encoding: None,
+        });
+    }
+    let display = path.display();
+    let ledger_file = LedgerFile {
+        path: path.to_path_buf(),
+        file: Input::File(
+            std::fs::File::open(path).context(format!("unable to open '{}'", display))?,
+        ),
+        entries: Vec::new(),
+        bom: false,
+        line_ending: LineEnding::default(),
+        encoding: None,
+    };
+    Ok(ledger_file)
+}
+
+/// Returns the encoding with the given label, e.g. `latin1` or `windows-1252`.
+/// UTF-8 returns `None` because it needs no transcoding.
+pub fn parse_encoding(label: &str) -> Result<Option<&'static Encoding>> {
+    let encoding = Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| anyhow!("Unknown encoding '{}'", label))?;
+    if encoding.output_encoding() != encoding {
+        return Err(anyhow!("The encoding {} isn't supported", encoding.name()));
+    }
+    Ok((encoding != encoding_rs::UTF_8).then_some(encoding))
+}
+
+/// Transcodes the content of the file at `path` from `encoding` to UTF-8.
+pub fn decode(bytes: &[u8], encoding: &'static Encoding, path: &Path) -> Result<String> {
+    encoding
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .map(|content| content.into_owned())
+        .ok_or_else(|| anyhow!("'{}' isn't valid {}", path.display(), encoding.name()))
+}
+
+/// Returns the path of the backup of a beancount file.
+/// The new name is old_name_backup.old_extension, files without an extension get `.beancount`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!(
+        "{}_backup.{}",
+        path.file_stem()
+            .unwrap_or(OsStr::new("finances"))
+            .to_string_lossy(),
+        path.extension()
+            .unwrap_or(OsStr::new("beancount"))
+            .to_string_lossy()
+    ))
+}
+
+/// Returns true if both paths lead to the same existing file, e.g. through a symlink.
+pub fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Returns the path of the temporary file next to `path` that the sorted file is written to
+/// before it replaces the file at `path`, e.g. finances.beancount.tmp.1234.
+/// The id of the process keeps two runs from writing the same temporary file.
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut file_name = path
+        .file_name()
+        .unwrap_or(OsStr::new("finances.beancount"))
+        .to_owned();
+    file_name.push(format!(".tmp.{}", std::process::id()));
+    path.with_file_name(file_name)
+}
+
+/// Returns the path of a backup of a beancount file inside of `dir`.
+/// The UTC time of the backup is appended to the name so that successive backups don't collide,
+/// e.g. finances_backup_20230102T030405Z.beancount. Backups within the same second are
+/// numbered with `counter`, e.g. finances_backup_20230102T030405Z_1.beancount.
+pub fn timestamped_backup_path(
+    path: &Path,
+    dir: &Path,
+    time: &DateTime<Utc>,
+    counter: usize,
+) -> PathBuf {
+    let file_name = backup_path(path);
+    let counter = if counter > 0 {
+        format!("_{}", counter)
+    } else {
+        String::new()
+    };
+    dir.join(format!(
+        "{}_{}{}.{}",
+        file_name
+            .file_stem()
+            .unwrap_or(OsStr::new("finances_backup"))
+            .to_string_lossy(),
+        time.format("%Y%m%dT%H%M%SZ"),
+        counter,
+        file_name
+            .extension()
+            .unwrap_or(OsStr::new("beancount"))
+            .to_string_lossy()
+    ))
+}
+
+/// Creates a backup of the original beancount file and returns its path.
+/// Without `backup_dir` the backup is written to [backup_path], otherwise to
+/// [timestamped_backup_path] inside of `backup_dir`, which is created if necessary.
+/// A timestamped backup never overwrites an earlier one.
+pub fn backup_file(path: &Path, backup_dir: Option<&Path>) -> Result<PathBuf> {
+    let path_backup = match backup_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir).context(format!(
+                "unable to create backup directory '{}'",
+                dir.display()
+            ))?;
+            reserve_backup_path(path, dir, &Utc::now())?
+        }
+        None => backup_path(path),
+    };
+    std::fs::copy(path, &path_backup).context(format!(
+        "unable to backup '{}' to '{}'",
+        path.display(),
+        path_backup.display()
+    ))?;
+    Ok(path_backup)
+}
+
+/// Creates an empty file at the first [timestamped_backup_path] that doesn't exist yet
+/// and returns its path. Creating it reserves the name, even for another run at the same time.
+fn reserve_backup_path(path: &Path, dir: &Path, time: &DateTime<Utc>) -> Result<PathBuf> {
+    for counter in 0.. {
+        let path_backup = timestamped_backup_path(path, dir, time, counter);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path_backup)
+        {
+            Ok(_) => return Ok(path_backup),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                return Err(anyhow!(
+                    "unable to create backup '{}': {}",
+                    path_backup.display(),
+                    e
+                ))
+            }
+        }
+    }
+    unreachable!("there is always a free backup name")
+}
+
+// The regexes are compiled only once because they are used for every line of a file.
+lazy_static! {
+    static ref RE_DATE: Regex = Regex::new(r"^(\d{4}-[01]\d-[0-3]\d)").unwrap();
+    static ref RE_OPTION: Regex = Regex::new(r"^(option)").unwrap();
+    static ref RE_PLUGIN: Regex = Regex::new(r"^plugin\s").unwrap();
+    static ref RE_PUSHTAG: Regex = Regex::new(r"^pushtag\s+(#\S+)").unwrap();
+    static ref RE_POPTAG: Regex = Regex::new(r"^poptag\s+(#\S+)").unwrap();
+    static ref RE_PUSHMETA: Regex = Regex::new(r"^pushmeta\s+([^:\s]+):").unwrap();
+    static ref RE_POPMETA: Regex = Regex::new(r"^popmeta\s+([^:\s]+):").unwrap();
+    static ref RE_INCLUDE: Regex = Regex::new(r"^include\s").unwrap();
+    static ref RE_COMMENT: Regex = Regex::new(r"^(;+)").unwrap();
+    static ref RE_ORG_HEADING: Regex = Regex::new(r"^\*+(\s|$)").unwrap();
+    static ref RE_INDENTED: Regex = Regex::new(r"^[ \t]+\S").unwrap();
+    // lines with only whitespace are empty as well, e.g. between the postings of a transaction
+    static ref RE_EMPTY: Regex = Regex::new(r"^\s*$").unwrap();
+    static ref RE_MONTH_HEADER: Regex = Regex::new(r"^; ---- \d{4}-\d{2} ----$").unwrap();
+    static ref RE_DIRECTIVE: Regex = Regex::new(r"^\d{4}-[01]\d-[0-3]\d (\w+|\*|!)").unwrap();
+    static ref RE_ACCOUNT_DIRECTIVE: Regex = Regex::new(
+        r"^\d{4}-[01]\d-[0-3]\d\s+(?:open|close)\s+(\p{Lu}[\p{L}\p{N}-]*(?::[\p{Lu}\p{N}][\p{L}\p{N}-]*)+)"
+    )
+    .unwrap();
+    static ref RE_COMMODITY_DIRECTIVE: Regex =
+        Regex::new(r"^\d{4}-[01]\d-[0-3]\d\s+commodity\s+([A-Z][A-Z0-9'._-]*)").unwrap();
+    static ref RE_PRICE_DIRECTIVE: Regex =
+        Regex::new(r"^\d{4}-[01]\d-[0-3]\d\s+price\s+([A-Z][A-Z0-9'._-]*)").unwrap();
+    static ref RE_STRING: Regex = Regex::new(r#""((?:[^"\\]|\\.)*)""#).unwrap();
+    static ref RE_AMOUNT: Regex =
+        Regex::new(r"(?:^|\s)(-?\d[\d,]*(?:\.\d+)?)\s+[A-Z][A-Z0-9'._-]*").unwrap();
+}
+
+/// Identifies the [Line] type of a given [str].
+pub fn get_line_type(line: &str, n: &usize) -> Result<Line> {
+    if RE_DATE.is_match(line) {
+        let matches = RE_DATE.captures(line);
+        let date_match = match matches {
+            Some(m) => m.get(1),
+            None => unreachable!(),
+        };
+        let date = match date_match {
+            Some(d) => d.as_str(),
+            None => unreachable!(),
+        };
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|e| anyhow!("Invalid date {} in line {} ({}):\n\"{}\"", date, n, e, line))?;
+        Ok(Line::Date(date))
+    } else if RE_OPTION.is_match(line) {
+        Ok(Line::Option)
+    } else if RE_PLUGIN.is_match(line) {
+        Ok(Line::Plugin)
+    } else if let Some(captures) = RE_PUSHTAG.captures(line) {
+        Ok(Line::PushTag(captures[1].to_owned()))
+    } else if let Some(captures) = RE_POPTAG.captures(line) {
+        Ok(Line::PopTag(captures[1].to_owned()))
+    } else if let Some(captures) = RE_PUSHMETA.captures(line) {
+        Ok(Line::PushMeta(captures[1].to_owned()))
+    } else if let Some(captures) = RE_POPMETA.captures(line) {
+        Ok(Line::PopMeta(captures[1].to_owned()))
+    } else if RE_INCLUDE.is_match(line) {
+        Ok(Line::Include)
+    // indentation has to be tested before comment so that an indented comment stays
+    // inside of its entry
+    } else if RE_INDENTED.is_match(line) {
+        Ok(Line::Indent)
+    // month headers have to be tested before comment, section headings are found by find_entries
+    } else if RE_MONTH_HEADER.is_match(line) {
+        Ok(Line::Section)
+    } else if RE_COMMENT.is_match(line) {
+        Ok(Line::Comment)
+    // org-mode headings are ignored by beancount, a transaction flag always follows a date
+    } else if RE_ORG_HEADING.is_match(line) {
+        Ok(Line::OrgHeading)
+    } else if RE_EMPTY.is_match(line) {
+        Ok(Line::Empty)
+    } else {
+        Err(anyhow!("Can't define line {}: \"{}\"", n, line))
+    }
+}
+
+/// Creates an error formatted as `path:line: message` that shows the line `n` of the file
+/// together with the two lines above and below it.
+fn located_error(path: &Path, n: usize, message: impl Display) -> anyhow::Error {
+    let mut text = format!("{}:{}: {}", path.display(), n, message);
+    if let Ok(content) = std::fs::read_to_string(path) {
+        let width = (n + 2).to_string().len();
+        for (i, line) in content
+            .trim_start_matches(BOM)
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line))
+            .skip(n.saturating_sub(3))
+            .take_while(|(i, _)| *i <= n + 2)
+        {
+            let marker = if i == n { '>' } else { ' ' };
+            text.push_str(&format!(
+                "\n{} {:>width$} | {}",
+                marker,
+                i,
+                line,
+                width = width
+            ));
+        }
+    }
+    anyhow!(text)
+}
+
+/// Returns the first word (or the content of the first quoted string) of the text
+/// following the directive keyword. This is the payee of a transaction, the account
+/// of an `open`/`close` or the currency of a `price` directive.
+fn get_secondary_key(rest: &str) -> Option<String> {
+    let rest = rest.trim_start();
+    if let Some(quoted) = rest.strip_prefix('"') {
+        quoted.split('"').next().map(|s| s.to_owned())
+    } else {
+        rest.split_whitespace().next().map(|s| s.to_owned())
+    }
+}
+
+/// Creates an [Entry] from a given string and a date.
+pub fn construct_dated_entry(line: &str, date: NaiveDate) -> Result<Entry> {
+    let matches = RE_DIRECTIVE.captures(line);
+    let directive_string = match &matches {
+        Some(m) => m.get(1).unwrap().as_str(), // unwrap is okay because this can only be a match
+        None if RE_DATE.is_match(line) && line[10..].starts_with(|c: char| !c.is_whitespace()) => {
+            return Err(anyhow!(
+                "Couldn't find the entry type of \"{}\". Did you forget a space after the date?",
+                line
+            ))
+        }
+        None => return Err(anyhow!("Couldn't find the entry type of \"{}\".", line)),
+    };
+    let entry_type = match directive_string {
+        // txn and all the flags that beancount allows for transactions
+        "*" | "!" | "txn" | "P" | "S" | "T" | "C" | "U" | "R" | "M" => EntryType::Transaction,
+        "commodity" => EntryType::Commodity,
+        "price" => EntryType::Price,
+        "open" => EntryType::Account,
+        "close" => EntryType::ClosedAccount,
+        "balance" => EntryType::Balance,
+        "pad" => EntryType::Pad,
+        "event" => EntryType::Event,
+        "document" => EntryType::Document,
+        "custom" => EntryType::Custom,
+        "note" => EntryType::Note,
+        "query" => EntryType::Query,
+        _ => EntryType::OtherEntry,
+    };
+    // unwrap is okay because the whole regex matched
+    let rest = &line[matches.unwrap().get(0).unwrap().end()..];
+    let entry = Entry {
+        content: line.to_owned(),
+        date: Some(date),
+        entry_type,
+        secondary_key: get_secondary_key(rest),
+        line_number: 0,
+    };
+    Ok(entry)
+}
+
+/// Returns true if a double-quoted string is still open at the end of `line`.
+/// `open_before` tells if the line starts inside of a string.
+/// Quotes inside of a comment and escaped quotes inside of a string are ignored.
+fn string_open_after(line: &str, open_before: bool) -> bool {
+    let mut in_string = open_before;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if in_string => {
+                chars.next();
+            }
+            '"' => in_string = !in_string,
+            ';' if !in_string => break,
+            _ => {}
+        }
+    }
+    in_string
+}
+
+/// What happens with a comment that is separated from the next entry by an empty line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum OrphanComments {
+    /// Keep the comment as its own entry that is sorted right behind the entry in front of it.
+    #[default]
+    Keep,
+    /// Attach the comment to the next entry like a comment directly above it.
+    AttachNext,
+    /// Attach the comment to the entry in front of it.
+    AttachPrev,
+}
+
+impl FromStr for OrphanComments {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "keep" => Ok(OrphanComments::Keep),
+            "attach-next" => Ok(OrphanComments::AttachNext),
+            "attach-prev" => Ok(OrphanComments::AttachPrev),
+            _ => Err(anyhow!(
+                "Unknown value '{}'. Use keep, attach-next or attach-prev.",
+                s
+            )),
+        }
+    }
+}
+
+/// Places the comment at the end of `entries` that is not directly followed by an entry.
+/// With [OrphanComments::AttachPrev] it is appended to the entry in front of it, otherwise it
+/// keeps the date and secondary key of that entry so that it is sorted right behind it.
+fn place_orphan_comment(entries: &mut Vec<Entry>, orphan_comments: OrphanComments) {
+    let comment = match entries.pop() {
+        Some(comment) => comment,
+        None => return,
+    };
+    match entries.last_mut() {
+        Some(previous) if orphan_comments == OrphanComments::AttachPrev => {
+            previous.content = previous.content.to_owned() + "\n" + &comment.content;
+        }
+        Some(previous) => {
+            let date = previous.date;
+            let secondary_key = previous.secondary_key.clone();
+            entries.push(Entry {
+                date,
+                secondary_key,
+                ..comment
+            });
+        }
+        None => entries.push(comment),
+    }
+}
+
+/// Options that change how [find_entries] splits a file into entries.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Number of lines at the beginning of the file that are kept as they are in the Header section.
+    pub skipn: usize,
+    /// Return an error instead of keeping a pushmeta block together if sorting would split it.
+    pub strict_meta_blocks: bool,
+    /// Replace include directives by the entries of the included files.
+    pub follow_includes: bool,
+    /// What to do with comments that don't belong directly to the next entry.
+    pub orphan_comments: OrphanComments,
+    /// Attach a comment directly below an entry to that entry instead of the next one.
+    pub comments_follow: bool,
+    /// Keep entries with an invalid date together with their indented lines as other entries
+    /// instead of returning an error.
+    pub lenient_dates: bool,
+    /// Keep lines that can't be classified as other entries instead of returning an error.
+    pub lenient: bool,
+    /// Keep org-mode headings like comments instead of removing them.
+    pub keep_org_headings: bool,
+    /// Lines with this decoration are removed like the default section headings.
+    pub section_deco: SectionDeco,
+    /// Keep the section headings of the file as entries instead of removing them.
+    pub keep_sections: bool,
+    /// The encoding of the file if it isn't UTF-8, see [parse_encoding]
+    pub encoding: Option<&'static Encoding>,
+    /// Keep comments that aren't directly above a dated entry behind the entry in front of them
+    /// instead of sorting them on their own. Overrides [ParseOptions::orphan_comments].
+    pub keep_leading_comments: bool,
+}
+
+impl ParseOptions {
+    /// Returns how comments that don't belong to the next entry are placed.
+    /// With [ParseOptions::keep_leading_comments] they always stay with the entry in front of them.
+    fn standalone_comments(&self) -> OrphanComments {
+        if self.keep_leading_comments {
+            OrphanComments::AttachPrev
+        } else {
+            self.orphan_comments
+        }
+    }
+}
+
+/// Removes the line above the name of a section heading from the last entry,
+/// see [banner_deco]. It is kept as a section entry of its own with [ParseOptions::keep_sections].
+fn remove_banner_rule(entries: &mut Vec<Entry>, deco: char, options: &ParseOptions) {
+    // the skipped lines are kept as they are
+    let Some(last_entry) = entries
+        .last_mut()
+        .filter(|e| e.entry_type != EntryType::Header)
+    else {
+        return;
+    };
+    let (rest, rule) = match last_entry.content.rsplit_once('\n') {
+        Some((rest, rule)) => (Some(rest.to_owned()), rule.to_owned()),
+        None => (None, last_entry.content.clone()),
+    };
+    if !is_banner_rule(&rule, deco) {
+        return;
+    }
+    let line_number = last_entry.line_number + last_entry.content.lines().count() - 1;
+    match rest {
+        Some(rest) => last_entry.content = rest,
+        None => {
+            entries.pop();
+        }
+    }
+    if options.keep_sections {
+        entries.push(Entry {
+            content: rule,
+            date: None,
+            entry_type: EntryType::Section,
+            secondary_key: None,
+            line_number,
+        });
+    }
+}
+
+/// A pushtag or pushmeta block that has not been closed yet.
+struct OpenBlock {
+    directive: &'static str,
+    name: String,
+    line: usize,
+    // index of the first entry of the block
+    start: usize,
+    first_date: Option<NaiveDate>,
+    last_date: Option<NaiveDate>,
+}
+
+/// Splits the content of a [LedgerFile] into [Entry]s.
+/// The first `options.skipn` lines are kept as they are in the Header section.
+pub fn find_entries(ledger_file: LedgerFile, options: &ParseOptions) -> Result<LedgerFile> {
+    find_entries_following_includes(ledger_file, options, &mut Vec::new())
+}
+
+/// Reads the entries of the file included by an include directive.
+/// `include_stack` holds the files that are currently being parsed to detect circular includes.
+fn find_included_entries(
+    include_line: &str,
+    including_path: &Path,
+    options: &ParseOptions,
+    include_stack: &mut Vec<PathBuf>,
+) -> Result<Vec<Entry>> {
+    let included = include_line
+        .split('"')
+        .nth(1)
+        .with_context(|| format!("Can't find the included file in \"{}\"", include_line))?;
+    let path = including_path
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(included);
+    let included_options = ParseOptions {
+        skipn: 0,
+        ..options.clone()
+    };
+    let ledger_file = find_entries_following_includes(
+        read_file(&path).context(format!("unable to follow '{}'", include_line))?,
+        &included_options,
+        include_stack,
+    )?;
+    Ok(ledger_file.entries)
+}
+
+fn find_entries_following_includes(
+    mut ledger_file: LedgerFile,
+    options: &ParseOptions,
+    include_stack: &mut Vec<PathBuf>,
+) -> Result<LedgerFile> {
+    if options.follow_includes {
+        let canonical_path = ledger_file
+            .path
+            .canonicalize()
+            .unwrap_or_else(|_| ledger_file.path.clone());
+        if include_stack.contains(&canonical_path) {
+            return Err(anyhow!(
+                "Circular include of '{}'",
+                ledger_file.path.display()
+            ));
+        }
+        include_stack.push(canonical_path);
+    }
+    let n_skip = options.skipn;
+    let file_path = ledger_file.path.clone();
+    if let Some(encoding) = options.encoding {
+        let mut bytes = Vec::new();
+        ledger_file.file.read_to_end(&mut bytes)?;
+        let content = decode(&bytes, encoding, &file_path)?;
+        ledger_file.file = Input::Decoded(io::Cursor::new(content.into_bytes()));
+        ledger_file.encoding = Some(encoding);
+    }
+    let mut reader = BufReader::new(&mut ledger_file.file);
+    // A byte order mark would prevent the first line from being recognized
+    if reader.fill_buf()?.starts_with(BOM.to_string().as_bytes()) {
+        reader.consume(BOM.len_utf8());
+        ledger_file.bom = true;
+    }
+    // number of lines ending with \r\n and with \n only
+    let (mut crlf_lines, mut lf_lines) = (0, 0);
+    // like reader.lines() but counts the line endings that lines() removes silently
+    let mut lines = std::iter::from_fn(|| {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                if line.ends_with("\r\n") {
+                    line.truncate(line.len() - 2);
+                    crlf_lines += 1;
+                } else if line.ends_with('\n') {
+                    line.pop();
+                    lf_lines += 1;
+                }
+                Some(Ok(line))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    })
+    .peekable();
+    let mut line_vec: Vec<(String, Line)> = Vec::new();
+    let mut open_blocks: Vec<OpenBlock> = Vec::new();
+    // true if a double-quoted string of the last entry continues on the next line
+    let mut open_string = false;
+    // number of empty lines since the last non-empty line
+    let mut blank_lines: usize = 0;
+    // true if the last entry is a comment that is still waiting for the entry it belongs to
+    let mut pending_comment = false;
+    // number of lines that were kept because of options.lenient
+    let mut unclassified_lines = 0;
+    // date ranges of the closed pushmeta blocks: (first date, last date, name, line number)
+    let mut meta_blocks: Vec<(NaiveDate, NaiveDate, String, usize)> = Vec::new();
+    // 1-based number of the last line that was read from the file
+    let mut n: usize = 0;
+    // decoration of the section heading whose name was on the last line, see banner_deco
+    let mut banner: Option<char> = None;
+    for _ in 0..n_skip {
+        n += 1;
+        let line: String = lines
+            .next()
+            .context("skipped more lines than are available in the file")??;
+        let entry = Entry {
+            content: line,
+            date: None,
+            entry_type: EntryType::Header,
+            secondary_key: None,
+            line_number: n,
+        };
+        ledger_file.entries.push(entry)
+    }
+
+    while let Some(line) = lines.next() {
+        n += 1;
+        let line: String = line?;
+        // If a string of the last entry is still open the line belongs to it
+        if open_string {
+            let last_entry = ledger_file.entries.last_mut().unwrap(); // unwrap is save because a string can only be opened by an entry
+            last_entry.content = last_entry.content.to_owned() + "\n" + &line;
+            open_string = string_open_after(&line, true);
+            continue;
+        }
+        // with keep_sections headings you wrote yourself like `;€€€€ My Transactions` are kept
+        // as headings. Nothing is removed then, so the decoration at the start is enough.
+        let deco = options.section_deco.deco.to_string();
+        let line_type = if options.keep_sections
+            && line
+                .strip_prefix(';')
+                .is_some_and(|rest| rest.starts_with(&deco.repeat(options.section_deco.count)))
+        {
+            Ok(Line::Section)
+        } else {
+            get_line_type(&line, &n)
+        };
+        let line_type: Line = match line_type {
+            // With lenient_dates a line with an invalid date is kept as an other entry
+            // that takes its postings and metadata like any other dated entry
+            Err(error) if options.lenient_dates && RE_DATE.is_match(&line) => {
+                eprintln!(
+                    "Warning: {}\nThe entry is kept in \"Other Entries\".",
+                    error
+                );
+                Line::Unclassified
+            }
+            // With lenient a line that can't be classified is kept as an other entry
+            Err(error) if options.lenient => {
+                eprintln!("Warning: {}\nThe line is kept in \"Other Entries\".", error);
+                unclassified_lines += 1;
+                Line::Unclassified
+            }
+            line_type => line_type.map_err(|e| located_error(&file_path, n, e))?,
+        };
+        // headings are removed whatever decoration they were written with, but only complete
+        // ones with a rule above and below the name. Other comments can look like a part of them.
+        let line_type = match line_type {
+            Line::Comment if banner.is_some_and(|deco| is_banner_rule(&line, deco)) => {
+                Line::Section
+            }
+            Line::Comment => match banner_deco(&line) {
+                Some(deco)
+                    if blank_lines == 0
+                        && ends_with_banner_rule(&ledger_file.entries, deco)
+                        && matches!(lines.peek(), Some(Ok(next)) if is_banner_rule(next, deco)) =>
+                {
+                    banner = Some(deco);
+                    // the line above the name is part of the last entry
+                    remove_banner_rule(&mut ledger_file.entries, deco, options);
+                    pending_comment = ledger_file
+                        .entries
+                        .last()
+                        .is_some_and(|e| e.entry_type == EntryType::Comment);
+                    Line::Section
+                }
+                _ => Line::Comment,
+            },
+            line_type => line_type,
+        };
+        if !matches!(line_type, Line::Section) {
+            banner = None;
+        }
+        line_vec.push((line.clone(), line_type.clone()));
+        let mut entry: Entry = match line_type {
+            // If line has a date: create a dated entry
+            Line::Date(d) => {
+                for block in open_blocks.iter_mut() {
+                    block.first_date = Some(block.first_date.map_or(d, |b| b.min(d)));
+                    block.last_date = Some(block.last_date.map_or(d, |b| b.max(d)));
+                }
+                Entry {
+                    line_number: n,
+                    ..construct_dated_entry(&line, d)
+                        .map_err(|e| located_error(&file_path, n, e))?
+                }
+            }
+            // If line is an option: create an entry with default date
+            Line::Option => Entry {
+                content: line.to_owned(),
+                date: None,
+                entry_type: EntryType::Option,
+                secondary_key: None,
+                line_number: n,
+            },
+            // If line is a plugin: create an entry with default date to keep the order of the plugins
+            Line::Plugin => Entry {
+                content: line.to_owned(),
+                date: None,
+                entry_type: EntryType::Plugin,
+                secondary_key: None,
+                line_number: n,
+            },
+            // If line is an include: create an entry with default date to keep the order of the includes
+            Line::Include => Entry {
+                content: line.to_owned(),
+                date: None,
+                entry_type: EntryType::Include,
+                secondary_key: None,
+                line_number: n,
+            },
+            // If line opens or closes a block: create an entry which is later merged into the block
+            Line::PushTag(_) | Line::PopTag(_) | Line::PushMeta(_) | Line::PopMeta(_) => Entry {
+                content: line.to_owned(),
+                date: None,
+                entry_type: EntryType::Block,
+                secondary_key: None,
+                line_number: n,
+            },
+            // If line is a section heading: keep it if the sections of the file are kept, otherwise ignore it
+            Line::Section if options.keep_sections => Entry {
+                content: line.to_owned(),
+                date: None,
+                entry_type: EntryType::Section,
+                secondary_key: None,
+                line_number: n,
+            },
+            Line::Section => continue,
+            // If line is an org-mode heading: ignore it unless it should be kept like a comment
+            Line::OrgHeading if !options.keep_org_headings => continue,
+            // If line is a comment: create an entry with default date
+            Line::Comment | Line::OrgHeading => Entry {
+                content: line.to_owned(),
+                date: None,
+                entry_type: EntryType::Comment,
+                secondary_key: None,
+                line_number: n,
+            },
+            // If line couldn't be classified: create an other entry with default date
+            Line::Unclassified => Entry {
+                content: line.to_owned(),
+                date: None,
+                entry_type: EntryType::OtherEntry,
+                secondary_key: None,
+                line_number: n,
+            },
+            // If line is an indented line: create an entry with default date
+            Line::Indent => Entry {
+                content: line.to_owned(),
+                date: None,
+                entry_type: EntryType::Indented,
+                secondary_key: None,
+                line_number: n,
+            },
+            // If line is empty: remember it in case it is inside of a multi-line entry
+            Line::Empty => {
+                blank_lines += 1;
+                continue;
+            }
+        };
+        let preceding_blank_lines = mem::take(&mut blank_lines);
+        // With comments_follow a comment directly below an entry belongs to that entry
+        if options.comments_follow
+            && entry.entry_type == EntryType::Comment
+            && preceding_blank_lines == 0
+            && !pending_comment
+        {
+            if let Some(last_entry) = ledger_file.entries.last_mut() {
+                last_entry.content = last_entry.content.to_owned() + "\n" + &line;
+                continue;
+            }
+        }
+        // If the last Entry is a Comment directly above this line then add it to the content of this Entry
+        if pending_comment {
+            // with keep_leading_comments only a dated entry takes the comment above it
+            let standalone = preceding_blank_lines > 0
+                || (options.keep_leading_comments
+                    && entry.date.is_none()
+                    && entry.entry_type != EntryType::Indented);
+            if standalone && options.standalone_comments() != OrphanComments::AttachNext {
+                place_orphan_comment(&mut ledger_file.entries, options.standalone_comments());
+            } else {
+                let comment_entry = ledger_file.entries.pop().unwrap(); // unwrap is save because a pending comment is always the last entry
+                entry.content = comment_entry.content + "\n" + &entry.content;
+                entry.line_number = comment_entry.line_number;
+            }
+        }
+        pending_comment = entry.entry_type == EntryType::Comment;
+        // Replace an include by the entries of the included file and keep a leading comment
+        if entry.entry_type == EntryType::Include && options.follow_includes {
+            let mut included_entries =
+                find_included_entries(&line, &ledger_file.path, options, include_stack)?;
+            if let (Some(comment), Some(first)) = (
+                entry.content.strip_suffix(line.as_str()),
+                included_entries.first_mut(),
+            ) {
+                first.content = comment.to_owned() + &first.content;
+            }
+            ledger_file.entries.append(&mut included_entries);
+            continue;
+        }
+        // If the line is indented and the last entry was either a Transaction or a Commodity then add its content to the previous Entrys content
+        if let EntryType::Indented = entry.entry_type {
+            let last_entry = ledger_file.entries.pop().ok_or_else(|| {
+                located_error(
+                    &file_path,
+                    n,
+                    "Misplaced indented line at the start of the file",
+                )
+            })?;
+            // continue only if last line was a MultiLine-Entry
+            if last_entry.entry_type.takes_indented_lines() {
+                // keep a blank line between the indented lines of an entry, but never more than one
+                let content_new = last_entry.content.to_owned()
+                    + &"\n".repeat(preceding_blank_lines.min(1) + 1)
+                    + &entry.content;
+                let new_entry = Entry {
+                    content: content_new,
+                    date: last_entry.date,
+                    entry_type: last_entry.entry_type,
+                    secondary_key: last_entry.secondary_key,
+                    line_number: last_entry.line_number,
+                };
+                ledger_file.entries.push(new_entry);
+            } else {
+                return Err(located_error(
+                    &ledger_file.path,
+                    n,
+                    format!(
+                        "Misplaced indented line, it doesn't belong to a transaction or another entry with metadata:\n\"{}\"",
+                        entry
+                    ),
+                ));
+            };
+        } else {
+            ledger_file.entries.push(entry.clone())
+        };
+        if let Line::Date(_) | Line::Indent | Line::Option | Line::Plugin = line_type {
+            open_string = string_open_after(&line, false);
+        }
+        // Keep everything between a pushtag and its poptag (or pushmeta and popmeta) together
+        // because moving a transaction out of the block would change its tags or metadata.
+        // Like in beancount the pushed tags are a set, they don't have to be popped in order.
+        match line_type {
+            Line::PushTag(name) | Line::PushMeta(name) => {
+                let directive = if line.starts_with("pushtag") {
+                    "pushtag"
+                } else {
+                    "pushmeta"
+                };
+                open_blocks.push(OpenBlock {
+                    directive,
+                    name,
+                    line: n,
+                    start: ledger_file.entries.len() - 1,
+                    first_date: None,
+                    last_date: None,
+                });
+            }
+            Line::PopTag(name) | Line::PopMeta(name) => {
+                let (push, pop) = if line.starts_with("poptag") {
+                    ("pushtag", "poptag")
+                } else {
+                    ("pushmeta", "popmeta")
+                };
+                let i_block = open_blocks
+                    .iter()
+                    .rposition(|b| b.directive == push && b.name == name)
+                    .ok_or_else(|| {
+                        located_error(
+                            &file_path,
+                            n,
+                            format!("{} {} without matching {}", pop, name, push),
+                        )
+                    })?;
+                let block = open_blocks.remove(i_block);
+                if options.strict_meta_blocks && push == "pushmeta" {
+                    if let Some(e) = ledger_file.entries[block.start..].iter().find(|e| {
+                        !matches!(
+                            e.entry_type.section_variant(),
+                            EntryType::Transaction | EntryType::Comment
+                        )
+                    }) {
+                        return Err(located_error(
+                            &file_path,
+                            e.line_number,
+                            format!(
+                                "pushmeta {} in line {} can't be kept together because this entry belongs to another section:\n\"{}\"",
+                                block.name, block.line, e
+                            ),
+                        ));
+                    }
+                    if let (Some(first), Some(last)) = (block.first_date, block.last_date) {
+                        meta_blocks.push((first, last, block.name.clone(), block.line));
+                    }
+                }
+                // a block that was opened inside this one is still open,
+                // it keeps the entries and dates of this block together with its own
+                if let Some(inner) = open_blocks.get_mut(i_block) {
+                    inner.start = block.start;
+                    inner.first_date = block.first_date;
+                    inner.last_date = block.last_date;
+                    continue;
+                }
+                let block_entries = ledger_file.entries.split_off(block.start);
+                let line_number = block_entries[0].line_number;
+                ledger_file.entries.push(Entry {
+                    content: block_entries
+                        .into_iter()
+                        .map(|e| e.content)
+                        .collect::<Vec<String>>()
+                        .join("\n"),
+                    date: block.first_date,
+                    entry_type: EntryType::Block,
+                    secondary_key: None,
+                    line_number,
+                });
+            }
+            _ => {}
+        }
+    }
+    if pending_comment {
+        place_orphan_comment(&mut ledger_file.entries, options.standalone_comments());
+    }
+    if unclassified_lines > 0 {
+        eprintln!(
+            "Warning: {} lines of '{}' could not be classified",
+            unclassified_lines,
+            ledger_file.path.display()
+        );
+    }
+    if crlf_lines > lf_lines {
+        ledger_file.line_ending = LineEnding::Crlf;
+    }
+    if let Some(block) = open_blocks.last() {
+        let pop = if block.directive == "pushtag" {
+            "poptag"
+        } else {
+            "popmeta"
+        };
+        return Err(located_error(
+            &file_path,
+            block.line,
+            format!("{} {} has no matching {}", block.directive, block.name, pop),
+        ));
+    }
+    if options.follow_includes {
+        include_stack.pop();
+    }
+    // Transactions outside of a pushmeta block that would be sorted into it
+    for (first, last, name, line) in meta_blocks {
+        if let Some(e) = ledger_file.entries.iter().find(|e| {
+            e.entry_type == EntryType::Transaction
+                && e.date.is_some_and(|date| first < date && date < last)
+        }) {
+            return Err(anyhow!(
+                "pushmeta {} in line {} can't be kept together because the transaction in line {} would be sorted into it:\n\"{}\"",
+                name,
+                line,
+                e.line_number,
+                e
+            ));
+        }
+    }
+    debug!(
+        "Found {} entries in {} lines of {}",
+        ledger_file.entries.len(),
+        n,
+        ledger_file.path.display()
+    );
+    Ok(ledger_file)
+}
+
+/// Removes all option entries whose content already appeared in an earlier option entry.
+/// Returns the removed entries. Used when merging multiple files.
+pub fn remove_duplicate_options(entries: &mut Vec<Entry>) -> Vec<Entry> {
+    let mut seen_options: Vec<String> = Vec::new();
+    let mut duplicates = Vec::new();
+    entries.retain(|entry| {
+        if entry.entry_type != EntryType::Option {
+            return true;
+        }
+        let option = entry.content.trim().to_owned();
+        if seen_options.contains(&option) {
+            duplicates.push(entry.clone());
+            false
+        } else {
+            seen_options.push(option);
+            true
+        }
+    });
+    duplicates
+}
+
+/// Returns the entries dated after `today`, e.g. because of a mistyped year.
+/// Comments are left out because they only carry the date of the entry in front of them.
+pub fn find_future_entries(entries: &[Entry], today: NaiveDate) -> Vec<&Entry> {
+    entries
+        .iter()
+        .filter(|e| e.entry_type != EntryType::Comment)
+        .filter(|e| e.date.is_some_and(|date| date > today))
+        .collect()
+}
+
+/// Returns pairs of an entry and a later entry with the same date whose content only differs in whitespace.
+/// Options, comments and the header are not compared.
+pub fn find_duplicates(entries: &[Entry]) -> Vec<(&Entry, &Entry)> {
+    let mut seen: HashMap<(Option<NaiveDate>, String), &Entry> = HashMap::new();
+    let mut duplicates = Vec::new();
+    for entry in entries {
+        if let EntryType::Option | EntryType::Comment | EntryType::Header | EntryType::Section =
+            entry.entry_type
+        {
+            continue;
+        }
+        let normalized = entry
+            .content
+            .lines()
+            .map(|l| l.split_whitespace().collect::<Vec<&str>>().join(" "))
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<String>>()
+            .join("\n");
+        match seen.get(&(entry.date, normalized.clone())) {
+            Some(first) => duplicates.push((*first, entry)),
+            None => {
+                seen.insert((entry.date, normalized), entry);
+            }
+        }
+    }
+    duplicates
+}
+
+/// Splits the entries into the ones inside of the date range from `from` to `to` (both inclusive)
+/// and the ones outside of it. Entries without a date like options are always inside of the range,
+/// comments belong to the range of the entry in front of them.
+pub fn split_by_date_range(
+    entries: Vec<Entry>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> (Vec<Entry>, Vec<Entry>) {
+    let mut in_range = true;
+    entries.into_iter().partition(|e| {
+        match e.entry_type {
+            EntryType::Comment => {}
+            EntryType::Header
+            | EntryType::Option
+            | EntryType::Plugin
+            | EntryType::Include
+            | EntryType::Section => in_range = true,
+            _ => {
+                in_range = from.is_none_or(|from| e.date.is_some_and(|date| date >= from))
+                    && to.is_none_or(|to| e.date.is_some_and(|date| date <= to))
+            }
+        }
+        in_range
+    })
+}
+
+/// How [sort_entries] orders entries of the same section with the same date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum TieBreak {
+    /// Order by the secondary key of the entries, see [construct_dated_entry]
+    #[default]
+    Date,
+    /// Order transactions by their payee, other entries by their secondary key
+    Payee,
+    /// Order transactions by their narration, other entries by their secondary key
+    Narration,
+    /// Order by the first amount of the entries
+    Amount,
+    /// Keep the order of the input, i.e. the order of the files and the line numbers within each
+    /// file (see [Entry::line_number]). Entries appended at the end of a sorted file stay behind
+    /// the entries of the same date that were already there.
+    #[cfg_attr(feature = "serde", serde(alias = "input"))]
+    Stable,
+}
+
+impl FromStr for TieBreak {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "date" => Ok(TieBreak::Date),
+            "payee" => Ok(TieBreak::Payee),
+            "narration" => Ok(TieBreak::Narration),
+            "amount" => Ok(TieBreak::Amount),
+            "stable" | "input" => Ok(TieBreak::Stable),
+            _ => Err(anyhow!(
+                "Unknown value '{}'. Use date, payee, narration, amount, stable or input.",
+                s
+            )),
+        }
+    }
+}
+
+/// Returns the contents of the quoted strings in the first line of an entry.
+fn header_strings(entry: &Entry) -> Vec<String> {
+    let first_line = entry
+        .content
+        .lines()
+        .find(|l| RE_DATE.is_match(l))
+        .unwrap_or("");
+    RE_STRING
+        .captures_iter(first_line)
+        .map(|c| c[1].to_owned())
+        .collect()
+}
+
+/// Returns the payee of a transaction or the secondary key of any other entry.
+/// A transaction with only one string has no payee.
+fn get_payee(entry: &Entry) -> Option<String> {
+    if entry.entry_type != EntryType::Transaction {
+        return entry.secondary_key.clone();
+    }
+    let mut strings = header_strings(entry);
+    if strings.len() >= 2 {
+        Some(strings.swap_remove(0))
+    } else {
+        None
+    }
+}
+
+/// Returns the narration of a transaction or the secondary key of any other entry.
+fn get_narration(entry: &Entry) -> Option<String> {
+    if entry.entry_type != EntryType::Transaction {
+        return entry.secondary_key.clone();
+    }
+    let strings = header_strings(entry);
+    match strings.len() {
+        0 => None,
+        1 => Some(strings[0].clone()),
+        _ => Some(strings[1].clone()),
+    }
+}
+
+/// Returns the first number followed by a currency in an entry, e.g. the amount of the first
+/// posting of a transaction or the price of a price directive. Strings are ignored.
+fn get_amount(entry: &Entry) -> Option<f64> {
+    entry
+        .content
+        .lines()
+        .filter(|l| !RE_COMMENT.is_match(l.trim_start()))
+        .find_map(|l| {
+            let line = RE_STRING.replace_all(l, "\"\"");
+            let line = line.split(';').next().unwrap_or("");
+            RE_AMOUNT
+                .captures(line)
+                .and_then(|c| c[1].replace(',', "").parse::<f64>().ok())
+        })
+}
+
+/// Compares two entries with the same date by the given [TieBreak].
+fn tiebreak_cmp(a: &Entry, b: &Entry, tiebreak: TieBreak) -> Ordering {
+    match tiebreak {
+        TieBreak::Date => a.secondary_key.cmp(&b.secondary_key),
+        TieBreak::Payee => get_payee(a).cmp(&get_payee(b)),
+        TieBreak::Narration => get_narration(a).cmp(&get_narration(b)),
+        TieBreak::Amount => get_amount(a)
+            .partial_cmp(&get_amount(b))
+            .unwrap_or(Ordering::Equal),
+        TieBreak::Stable => Ordering::Equal,
+    }
+}
+
+/// How [sort_entries] orders the entries of the Accounts section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum AccountsOrder {
+    /// Order by date like all other sections
+    #[default]
+    Date,
+    /// Order by the account name, entries of the same account by date
+    Name,
+}
+
+impl FromStr for AccountsOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "date" => Ok(AccountsOrder::Date),
+            "name" => Ok(AccountsOrder::Name),
+            _ => Err(anyhow!("Unknown value '{}'. Use date or name.", s)),
+        }
+    }
+}
+
+/// Returns the account of an `open` or `close` directive.
+/// Account components may contain numbers and non-ASCII letters, e.g. `Assets:Bank:Girokonto-2`
+/// or `Expenses:Café`.
+pub fn get_account_name(content: &str) -> Option<&str> {
+    content
+        .lines()
+        .find_map(|line| RE_ACCOUNT_DIRECTIVE.captures(line))
+        .map(|captures| captures.get(1).unwrap().as_str())
+}
+
+/// How [sort_entries] orders the entries of the Commodities section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum CommoditiesOrder {
+    /// Order by date like all other sections
+    #[default]
+    Date,
+    /// Order by the commodity symbol
+    Symbol,
+}
+
+impl FromStr for CommoditiesOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "date" => Ok(CommoditiesOrder::Date),
+            "symbol" => Ok(CommoditiesOrder::Symbol),
+            _ => Err(anyhow!("Unknown value '{}'. Use date or symbol.", s)),
+        }
+    }
+}
+
+/// Returns the symbol of a `commodity` directive, e.g. `EUR` or `VANGUARD.FTSE`.
+pub fn get_commodity_symbol(content: &str) -> Option<&str> {
+    content
+        .lines()
+        .find_map(|line| RE_COMMODITY_DIRECTIVE.captures(line))
+        .map(|captures| captures.get(1).unwrap().as_str())
+}
+
+/// How [sort_entries] orders the entries of the Prices section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum PricesOrder {
+    /// Order by date like all other sections
+    #[default]
+    Date,
+    /// Group the prices by the quoted commodity, each group ordered by date
+    Commodity,
+}
+
+impl FromStr for PricesOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "date" => Ok(PricesOrder::Date),
+            "commodity" => Ok(PricesOrder::Commodity),
+            _ => Err(anyhow!("Unknown value '{}'. Use date or commodity.", s)),
+        }
+    }
+}
+
+/// Returns the quoted commodity of a `price` directive, e.g. `GME` for
+/// `2021-01-21 price GME 420.69 EUR`.
+pub fn get_price_commodity(content: &str) -> Option<&str> {
+    content
+        .lines()
+        .find_map(|line| RE_PRICE_DIRECTIVE.captures(line))
+        .map(|captures| captures.get(1).unwrap().as_str())
+}
+
+/// Sorts the entries of a section by the name that `key` extracts from their content.
+/// The sort is stable, so entries with the same name stay ordered by date.
+/// A standalone comment carries the secondary key of the entry in front of it and stays behind it.
+fn sort_by_name(entries: &mut [Entry], key: fn(&str) -> Option<&str>) {
+    entries.sort_by_cached_key(|e| {
+        key(&e.content)
+            .map(|k| k.to_owned())
+            .or_else(|| e.secondary_key.clone())
+    });
+}
+
+/// Options that change how [sort_entries] orders the entries.
+#[derive(Debug, Clone)]
+pub struct SortOptions {
+    /// The names of the sections in the order they are written. See [parse_section_order]
+    pub section_order: Vec<String>,
+    /// The section that takes the entries of the sections missing in [SortOptions::section_order].
+    /// It has to be part of the section order itself.
+    pub fallback_section: Option<String>,
+    /// Sort custom directives into their own Custom section instead of the Other Entries section.
+    pub custom_section: bool,
+    /// Sort notes into their own Notes section instead of the Other Entries section.
+    pub notes_section: bool,
+    /// Sort queries into their own Queries section instead of the Other Entries section.
+    pub queries_section: bool,
+    /// Sort plugins into the Options section instead of their own Plugins section.
+    pub plugins_with_options: bool,
+    /// How entries with the same date are ordered.
+    pub tiebreak: TieBreak,
+    /// Sort the entries of each section from the newest to the oldest date.
+    pub descending: bool,
+    /// Sort only the Transactions and Prices sections from the newest to the oldest date.
+    pub reverse: bool,
+    /// Decoration of the section headings
+    pub section_deco: SectionDeco,
+    /// Insert a heading in front of the first transaction of every month, see [insert_month_headers]
+    pub month_headers: bool,
+    /// Group the entries by section without writing the section headings.
+    pub no_headings: bool,
+    /// Write the headings of empty sections as well.
+    pub always_show_sections: bool,
+    /// Keep the section headings found in the file and only sort the entries between them,
+    /// see [sort_kept_sections]. No headings are generated.
+    pub keep_sections: bool,
+    /// Sort balance assertions and pads into the Transactions section in front of the
+    /// transactions of the same date instead of into their own Balances section.
+    pub interleave_balances: bool,
+    /// Only write these sections (and the Header). All sections are written if it is empty.
+    pub filter_sections: Vec<String>,
+    /// How the entries of the Accounts section are ordered.
+    pub accounts_order: AccountsOrder,
+    /// How the entries of the Commodities section are ordered.
+    pub commodities_order: CommoditiesOrder,
+    /// How the entries of the Prices section are ordered.
+    pub prices_order: PricesOrder,
+}
+
+impl Default for SortOptions {
+    fn default() -> Self {
+        SortOptions {
+            section_order: SECTIONS.iter().map(|s| s.to_string()).collect(),
+            fallback_section: None,
+            custom_section: false,
+            notes_section: false,
+            queries_section: false,
+            plugins_with_options: false,
+            tiebreak: TieBreak::default(),
+            descending: false,
+            reverse: false,
+            interleave_balances: false,
+            section_deco: SectionDeco::default(),
+            month_headers: false,
+            no_headings: false,
+            always_show_sections: false,
+            keep_sections: false,
+            filter_sections: Vec::new(),
+            accounts_order: AccountsOrder::default(),
+            commodities_order: CommoditiesOrder::default(),
+            prices_order: PricesOrder::default(),
+        }
+    }
+}
+
+/// Creates the section order from a comma-separated list of section names.
+/// Returns the complete order and the sections that were missing in the list.
+/// Missing sections are appended in their default order, except for the Header which always comes first.
+pub fn parse_section_order(list: &str) -> Result<(Vec<String>, Vec<String>)> {
+    let mut section_order: Vec<String> = vec!["Header".to_string()];
+    for name in list.split(',').map(|name| name.trim()) {
+        if !SECTIONS.contains(&name) {
+            return Err(anyhow!(
+                "Unknown section \"{}\". Known sections are: {}",
+                name,
+                SECTIONS.join(", ")
+            ));
+        }
+        if name == "Header" {
+            continue;
+        }
+        if section_order.iter().any(|s| s == name) {
+            return Err(anyhow!("Section \"{}\" is given more than once", name));
+        }
+        section_order.push(name.to_string());
+    }
+    let missing: Vec<String> = SECTIONS
+        .iter()
+        .filter(|s| !section_order.iter().any(|o| o == *s))
+        .map(|s| s.to_string())
+        .collect();
+    section_order.extend(missing.iter().cloned());
+    Ok((section_order, missing))
+}
+
+/// Returns the [EntryType] of the section an entry is sorted into, taking the [SortOptions] into account.
+/// Entries of a section that is missing in [SortOptions::section_order] go to [SortOptions::fallback_section].
+fn get_sort_section(entry_type: &EntryType, options: &SortOptions) -> EntryType {
+    let section = get_configured_section(entry_type, options);
+    match &options.fallback_section {
+        Some(fallback)
+            if !options
+                .section_order
+                .iter()
+                .any(|s| EntryType::try_from(s.as_str()).ok().as_ref() == Some(&section)) =>
+        {
+            EntryType::try_from(fallback.as_str()).unwrap_or(section)
+        }
+        _ => section,
+    }
+}
+
+/// Returns the [EntryType] of the section an entry is sorted into without a fallback section.
+fn get_configured_section(entry_type: &EntryType, options: &SortOptions) -> EntryType {
+    match entry_type {
+        EntryType::Custom if options.custom_section => EntryType::Custom,
+        EntryType::Note if options.notes_section => EntryType::Note,
+        EntryType::Query if options.queries_section => EntryType::Query,
+        EntryType::Plugin if options.plugins_with_options => EntryType::Option,
+        EntryType::Balance | EntryType::Pad if options.interleave_balances => {
+            EntryType::Transaction
+        }
+        _ => entry_type.section_variant(),
+    }
+}
+
+/// Returns false for the sections of [SECTIONS] that [sort_entries] leaves out with the given options.
+pub fn is_written(section: &str, options: &SortOptions) -> bool {
+    !((section != "Header"
+        && !options.filter_sections.is_empty()
+        && !options.filter_sections.iter().any(|s| s == section))
+        || (section == "Custom" && !options.custom_section)
+        || (section == "Notes" && !options.notes_section)
+        || (section == "Queries" && !options.queries_section)
+        || (section == "Plugins" && options.plugins_with_options)
+        || (section == "Balances" && options.interleave_balances))
+}
+
+/// Counts the entries of every written section in the order of [SortOptions::section_order].
+/// Comments and the section headings aren't counted.
+pub fn count_sections(entries: &[Entry], options: &SortOptions) -> Result<Vec<(String, usize)>> {
+    let mut counts = Vec::new();
+    for section in options
+        .section_order
+        .iter()
+        .filter(|s| is_written(s, options))
+    {
+        let section_variant = EntryType::try_from(section.as_str())?;
+        let count = entries
+            .iter()
+            .filter(|e| !matches!(e.entry_type, EntryType::Comment | EntryType::Section))
+            .filter(|e| get_sort_section(&e.entry_type, options) == section_variant)
+            .count();
+        counts.push((section.to_owned(), count));
+    }
+    Ok(counts)
+}
+
+/// Returns the first and the last date of the dated entries.
+pub fn date_range(entries: &[Entry]) -> Option<(NaiveDate, NaiveDate)> {
+    entries
+        .iter()
+        .filter_map(|e| e.date)
+        .fold(None, |range, date| match range {
+            None => Some((date, date)),
+            Some((first, last)) => Some((first.min(date), last.max(date))),
+        })
+}
+
+/// Sorts a [Vec] of [Entry] by their section, their date and the [TieBreak] of the options
+/// A standalone comment is sorted into the section of the entry in front of it.
+/// The sort is stable, see [sort_entries_stable].
+pub fn sort_entries(entries: Vec<Entry>, options: &SortOptions) -> Result<Vec<Entry>> {
+    sort_entries_stable(entries, options)
+}
+
+/// Sorts the entries like [sort_entries] and guarantees that entries which are equal
+/// for the options (same section, same date and equal for the [TieBreak]) keep their
+/// relative order from `entries`. The position in `entries` is compared last,
+/// so this doesn't depend on the stability of the sort algorithm.
+pub fn sort_entries_stable(entries: Vec<Entry>, options: &SortOptions) -> Result<Vec<Entry>> {
+    debug!("Sorting {} entries with {:?}", entries.len(), options);
+    if options.keep_sections {
+        return Ok(sort_kept_sections(entries, options));
+    }
+    let mut previous_section = EntryType::Header;
+    let entries: Vec<(EntryType, usize, Entry)> = entries
+        .into_iter()
+        .enumerate()
+        .map(|(i, e)| {
+            if e.entry_type != EntryType::Comment {
+                previous_section = get_sort_section(&e.entry_type, options);
+            }
+            (previous_section.clone(), i, e)
+        })
+        .collect();
+    let mut sorted_entries: Vec<Entry> = Vec::new();
+    if let Some(name) = options
+        .filter_sections
+        .iter()
+        .find(|name| !SECTIONS.contains(&name.as_str()))
+    {
+        return Err(anyhow!(
+            "Unknown section \"{}\". Known sections are: {}",
+            name,
+            SECTIONS.join(", ")
+        ));
+    }
+    if let Some(fallback) = &options.fallback_section {
+        if !options.section_order.contains(fallback) {
+            return Err(anyhow!(
+                "The fallback section \"{}\" has to be part of the section order: {}",
+                fallback,
+                options.section_order.join(", ")
+            ));
+        }
+    }
+    for section in options.section_order.iter().map(|s| s.as_str()) {
+        if !is_written(section, options) {
+            continue;
+        }
+        let section_variant = EntryType::try_from(section)?;
+        let mut section_entries: Vec<(usize, Entry)> = entries
+            .iter()
+            .filter(|(s, _, _)| *s == section_variant)
+            .map(|(_, i, e)| (*i, e.clone()))
+            .collect();
+        // every section is sorted on its own because the direction can differ between sections
+        let descending = options.descending
+            || (options.reverse
+                && matches!(section_variant, EntryType::Transaction | EntryType::Price));
+        section_entries.sort_by(|(index_a, a), (index_b, b)| {
+            let by_date = if descending {
+                b.date.cmp(&a.date)
+            } else {
+                a.date.cmp(&b.date)
+            };
+            // balance assertions apply at the start of the day
+            let balances_first = || {
+                let is_transaction =
+                    |e: &Entry| !matches!(e.entry_type, EntryType::Balance | EntryType::Pad);
+                is_transaction(a).cmp(&is_transaction(b))
+            };
+            by_date
+                .then_with(|| {
+                    if options.interleave_balances {
+                        balances_first()
+                    } else {
+                        Ordering::Equal
+                    }
+                })
+                .then_with(|| tiebreak_cmp(a, b, options.tiebreak))
+                .then_with(|| index_a.cmp(index_b))
+        });
+        let mut section_entries: Vec<Entry> = section_entries.into_iter().map(|(_, e)| e).collect();
+        match section_variant {
+            EntryType::Account if options.accounts_order == AccountsOrder::Name => {
+                sort_by_name(&mut section_entries, get_account_name)
+            }
+            EntryType::Commodity if options.commodities_order == CommoditiesOrder::Symbol => {
+                sort_by_name(&mut section_entries, get_commodity_symbol)
+            }
+            EntryType::Price if options.prices_order == PricesOrder::Commodity => {
+                sort_by_name(&mut section_entries, get_price_commodity)
+            }
+            _ => {}
+        }
+        if section_variant == EntryType::Balance
+            || (section_variant == EntryType::Transaction && options.interleave_balances)
+        {
+            section_entries = move_pads_before_balances(section_entries);
+        }
+        if section_variant == EntryType::Transaction && options.month_headers {
+            section_entries = insert_month_headers(section_entries);
+        }
+        // create a new entry with the section heading, empty sections get none
+        if section != "Header"
+            && !options.no_headings
+            && (!section_entries.is_empty() || options.always_show_sections)
+        {
+            let section_entry = Entry {
+                content: options.section_deco.heading(section),
+                date: None,
+                entry_type: EntryType::Section,
+                secondary_key: None,
+                line_number: 0,
+            };
+            sorted_entries.push(section_entry);
+        }
+        sorted_entries.extend(section_entries)
+    }
+    Ok(sorted_entries)
+}
+
+/// Sorts the entries between the section headings that were kept by [find_entries] by their date
+/// and the [TieBreak] of the options. The headings stay where they are.
+fn sort_kept_sections(entries: Vec<Entry>, options: &SortOptions) -> Vec<Entry> {
+    let mut sorted_entries = Vec::with_capacity(entries.len());
+    let mut section_entries: Vec<(usize, Entry)> = Vec::new();
+    let sort_section = |section_entries: &mut Vec<(usize, Entry)>| {
+        section_entries.sort_by(|(index_a, a), (index_b, b)| {
+            let by_date = if options.descending {
+                b.date.cmp(&a.date)
+            } else {
+                a.date.cmp(&b.date)
+            };
+            by_date
+                .then_with(|| tiebreak_cmp(a, b, options.tiebreak))
+                .then_with(|| index_a.cmp(index_b))
+        });
+        mem::take(section_entries).into_iter().map(|(_, e)| e)
+    };
+    for (i, entry) in entries.into_iter().enumerate() {
+        if entry.entry_type == EntryType::Section {
+            sorted_entries.extend(sort_section(&mut section_entries));
+            sorted_entries.push(entry);
+        } else {
+            section_entries.push((i, entry));
+        }
+    }
+    sorted_entries.extend(sort_section(&mut section_entries));
+    sorted_entries
+}
+
+/// Checks the sorted entries for consecutive transactions that are more than `max_gap_days` apart,
+/// which might be a sign of missing data. Returns the dates of both transactions of every gap.
+pub fn validate_entries(entries: &[Entry], max_gap_days: u32) -> Vec<(NaiveDate, NaiveDate)> {
+    let dates: Vec<NaiveDate> = entries
+        .iter()
+        .filter(|e| e.entry_type == EntryType::Transaction)
+        .filter_map(|e| e.date)
+        .collect();
+    dates
+        .windows(2)
+        .filter(|pair| (pair[1] - pair[0]).num_days().abs() > i64::from(max_gap_days))
+        .map(|pair| (pair[0], pair[1]))
+        .collect()
+}
+
+/// Inserts a heading like `; ---- 2023-04 ----` in front of the first entry of every month.
+/// The headings are removed again by [find_entries] like the section headings.
+fn insert_month_headers(entries: Vec<Entry>) -> Vec<Entry> {
+    let mut with_headers = Vec::with_capacity(entries.len());
+    let mut month = None;
+    for entry in entries {
+        if let Some(date) = entry.date {
+            let entry_month = date.format("%Y-%m").to_string();
+            if month.as_ref() != Some(&entry_month) {
+                with_headers.push(Entry {
+                    content: format!("; ---- {} ----", entry_month),
+                    date: entry.date,
+                    entry_type: EntryType::Section,
+                    secondary_key: None,
+                    line_number: 0,
+                });
+                month = Some(entry_month);
+            }
+        }
+        with_headers.push(entry);
+    }
+    with_headers
+}
+
+/// Moves every pad directly in front of the balance assertion it pads.
+/// A pad belongs to the first balance of the same account dated on the same day
+/// or on the day after the pad. Pads without such a balance are left where they are.
+/// Orphan comments behind a pad are moved together with it.
+/// Pads and balances whose account couldn't be read aren't paired.
+fn move_pads_before_balances(entries: Vec<Entry>) -> Vec<Entry> {
+    let mut balances: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if let (EntryType::Balance, Some(account)) = (&entry.entry_type, &entry.secondary_key) {
+            balances.entry(account).or_default().push(i);
+        }
+    }
+    // the pads and their orphan comments that are moved in front of each balance
+    let mut moved: HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut is_moved = vec![false; entries.len()];
+    for (i_pad, pad) in entries.iter().enumerate() {
+        let account = match (&pad.entry_type, &pad.secondary_key) {
+            (EntryType::Pad, Some(account)) => account.as_str(),
+            _ => continue,
+        };
+        let next_day = pad.date.and_then(|d| d.succ_opt());
+        let i_balance = balances.get(account).and_then(|balances| {
+            balances
+                .iter()
+                .copied()
+                .find(|i| entries[*i].date == pad.date || entries[*i].date == next_day)
+        });
+        if let Some(i_balance) = i_balance {
+            let i_end = (i_pad + 1..entries.len())
+                .find(|i| entries[*i].entry_type != EntryType::Comment)
+                .unwrap_or(entries.len());
+            is_moved[i_pad..i_end].fill(true);
+            moved.entry(i_balance).or_default().extend(i_pad..i_end);
+        }
+    }
+    let mut moved_entries = Vec::with_capacity(entries.len());
+    for (i, entry) in entries.iter().enumerate() {
+        if is_moved[i] {
+            continue;
+        }
+        if let Some(pads) = moved.get(&i) {
+            moved_entries.extend(pads.iter().map(|i_pad| entries[*i_pad].to_owned()));
+        }
+        moved_entries.push(entry.to_owned());
+    }
+    moved_entries
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Datelike, TimeZone};
+    use std::mem::discriminant;
+
+    use super::*;
+    use std::env;
+
+    // TODO write setup struct
+    #[allow(dead_code)]
+    struct Setup {
+        good_entry: Entry,
+        bad_entry: Entry,
+    }
+
+    #[allow(dead_code)]
+    impl Setup {
+        fn new() -> Self {
+            let good_line: &str = "2022-04-17 * \"Schlosspark Pankow\" \"Brezel \"";
+            let good_date: NaiveDate = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+            Self {
+                good_entry: Entry {
+                    content: good_line.to_string(),
+                    date: Some(good_date),
+                    entry_type: EntryType::Transaction,
+                    secondary_key: None,
+                    line_number: 0,
+                },
+                bad_entry: Entry {
+                    content: good_line.to_string(),
+                    date: Some(good_date),
+                    // wrong entry type
+                    entry_type: EntryType::Account,
+                    secondary_key: None,
+                    line_number: 0,
+                },
+            }
+        }
+    }
+
+    /// Writes `content` to a temporary file named after `name` and parses it.
+    fn parse_str(name: &str, content: &str) -> Vec<Entry> {
+        parse_str_with(name, content, &ParseOptions::default())
+    }
+
+    fn parse_str_with(name: &str, content: &str, options: &ParseOptions) -> Vec<Entry> {
+        let path = env::temp_dir().join(format!("beancount-sort_test_{}.beancount", name));
+        std::fs::write(&path, content).unwrap();
+        let ledger_file = find_entries(read_file(&path).unwrap(), options).unwrap();
+        remove_file(&path).unwrap();
+        ledger_file.entries
+    }
+
+    #[test]
+    fn test_display() {
+        let entries = parse_str(
+            "display",
+            "2023-01-01 open Assets:Cash\n\
+             2023-01-02 * \"Bakery\" \"Bread\"\n\
+             \x20 Expenses:Food 3.00 EUR\n\
+             \x20 Assets:Cash\n",
+        );
+        assert_eq!(entries[0].to_string(), "2023-01-01 open Assets:Cash");
+        assert_eq!(entries[1].to_string(), entries[1].content);
+        assert_eq!(entries[0].entry_type.to_string(), "open");
+        assert_eq!(entries[1].entry_type.to_string(), "txn");
+        assert_eq!(EntryType::ClosedAccount.to_string(), "close");
+    }
+
+    #[test]
+    fn test_entry_type_try_from() {
+        assert_eq!(
+            discriminant(&EntryType::try_from("Header").unwrap()),
+            discriminant(&EntryType::Header)
+        );
+        assert_eq!(
+            discriminant(&EntryType::try_from("Accounts").unwrap()),
+            discriminant(&EntryType::Account)
+        );
+        assert_eq!(
+            discriminant(&EntryType::try_from("Options").unwrap()),
+            discriminant(&EntryType::Option)
+        );
+        assert_eq!(
+            discriminant(&EntryType::try_from("Plugins").unwrap()),
+            discriminant(&EntryType::Plugin)
+        );
+        assert_eq!(
+            discriminant(&EntryType::try_from("Includes").unwrap()),
+            discriminant(&EntryType::Include)
+        );
+        assert_eq!(
+            discriminant(&EntryType::try_from("Commodities").unwrap()),
+            discriminant(&EntryType::Commodity)
+        );
+        assert_eq!(
+            discriminant(&EntryType::try_from("Other Entries").unwrap()),
+            discriminant(&EntryType::OtherEntry)
+        );
+        assert_eq!(
+            discriminant(&EntryType::try_from("Prices").unwrap()),
+            discriminant(&EntryType::Price)
+        );
+        assert_eq!(
+            discriminant(&EntryType::try_from("Balances").unwrap()),
+            discriminant(&EntryType::Balance)
+        );
+        assert_eq!(
+            discriminant(&EntryType::try_from("Events").unwrap()),
+            discriminant(&EntryType::Event)
+        );
+        assert_eq!(
+            discriminant(&EntryType::try_from("Custom").unwrap()),
+            discriminant(&EntryType::Custom)
+        );
+        assert_eq!(
+            discriminant(&EntryType::try_from("Documents").unwrap()),
+            discriminant(&EntryType::Document)
+        );
+        assert_eq!(
+            discriminant(&EntryType::try_from("Transactions").unwrap()),
+            discriminant(&EntryType::Transaction)
+        );
+        assert!(EntryType::try_from("abcdefg")
+            .unwrap_err()
+            .to_string()
+            .starts_with("Unknown section \"abcdefg\""));
+        assert!(SECTIONS.iter().all(|s| EntryType::try_from(*s).is_ok()));
+    }
+    #[test]
+    fn test_sort_entries() {
+        let entries = vec![
+            Entry {
+                content: "3".to_string(),
+                date: NaiveDate::from_ymd_opt(2021, 1, 1),
+                entry_type: EntryType::Transaction,
+                secondary_key: None,
+                line_number: 0,
+            },
+            Entry {
+                content: "1".to_string(),
+                date: NaiveDate::from_ymd_opt(2021, 1, 2),
+                entry_type: EntryType::Option,
+                secondary_key: None,
+                line_number: 0,
+            },
+            Entry {
+                content: "2".to_string(),
+                date: NaiveDate::from_ymd_opt(2021, 1, 3),
+                entry_type: EntryType::Account,
+                secondary_key: None,
+                line_number: 0,
+            },
+        ];
+        let mut sorted_entries_function = sort_entries(entries, &SortOptions::default()).unwrap();
+        let sorted_entries_manual = [
+            Entry {
+                content: "1".to_string(),
+                date: NaiveDate::from_ymd_opt(2021, 1, 2),
+                entry_type: EntryType::Option,
+                secondary_key: None,
+                line_number: 0,
+            },
+            Entry {
+                content: "2".to_string(),
+                date: NaiveDate::from_ymd_opt(2021, 1, 3),
+                entry_type: EntryType::Account,
+                secondary_key: None,
+                line_number: 0,
+            },
+            Entry {
+                content: "3".to_string(),
+                date: NaiveDate::from_ymd_opt(2021, 1, 1),
+                entry_type: EntryType::Transaction,
+                secondary_key: None,
+                line_number: 0,
+            },
+        ];
+        let mut i = 0;
+        while i < sorted_entries_function.len() {
+            if mem::discriminant(&sorted_entries_function[i].entry_type)
+                == mem::discriminant(&EntryType::Section)
+            {
+                sorted_entries_function.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        // const SECTIONS: [&str; 7] = ["Header", "Options", "Accounts", "Commodities", "Other Entries", "Prices", "Transactions"];
+        assert_eq!(
+            sorted_entries_function[0].content,
+            sorted_entries_manual[0].content
+        );
+        assert_eq!(
+            sorted_entries_function[1].content,
+            sorted_entries_manual[1].content
+        );
+        assert_eq!(
+            sorted_entries_function[2].content,
+            sorted_entries_manual[2].content
+        );
+    }
+    #[test]
+    fn test_construct_dated_entry() {
+        let good_line: &str = "2022-04-17 * \"Schlosspark Pankow\" \"Brezel \"";
+        let good_date: NaiveDate = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+        let constructed_entry: Entry = construct_dated_entry(good_line, good_date).unwrap();
+        let good_entry: Entry = Entry {
+            content: good_line.to_string(),
+            date: Some(good_date),
+            entry_type: EntryType::Transaction,
+            secondary_key: Some("Schlosspark Pankow".to_string()),
+            line_number: 0,
+        };
+        assert_eq!(constructed_entry, good_entry);
+    }
+    #[test]
+    fn test_balances_section() {
+        let entries = parse_str(
+            "balances_section",
+            "2023-01-02 balance Assets:Checking 90.00 EUR\n\
+             2023-01-01 * \"Bakery\" \"Bread\"\n  Expenses:Food 10.00 EUR\n  Assets:Checking\n\
+             2023-01-01 balance Assets:Checking 100.00 EUR\n\
+             2023-01-01 note Assets:Checking \"Opened online\"\n",
+        );
+        let sorted_entries = sort_entries(entries, &SortOptions::default()).unwrap();
+        let position = |content: &str| {
+            sorted_entries
+                .iter()
+                .position(|e| e.content.lines().any(|l| l.starts_with(content)))
+                .unwrap()
+        };
+        let balances_heading = position(&format!(";{}Balances", DECO.repeat(NDECO)));
+        let transactions_heading = position(&format!(";{}Transactions", DECO.repeat(NDECO)));
+        let other_heading = position(&format!(";{}Other Entries", DECO.repeat(NDECO)));
+        let note = position("2023-01-01 note");
+        let first_balance = position("2023-01-01 balance");
+        let second_balance = position("2023-01-02 balance");
+        let transaction = position("2023-01-01 *");
+        assert!(other_heading < note && note < balances_heading);
+        assert!(balances_heading < first_balance);
+        assert!(first_balance < second_balance);
+        assert!(second_balance < transactions_heading);
+        assert!(transactions_heading < transaction);
+        assert_eq!(sorted_entries[first_balance].entry_type, EntryType::Balance);
+    }
+    #[test]
+    fn test_close_sorted_into_accounts() {
+        let date = NaiveDate::from_ymd_opt(2022, 12, 31).unwrap();
+        let closing = construct_dated_entry("2022-12-31 close Liabilities:OldCard", date).unwrap();
+        assert_eq!(closing.entry_type, EntryType::ClosedAccount);
+        assert_eq!(closing.entry_type.section_variant(), EntryType::Account);
+        let entries = vec![
+            Entry {
+                content: "2023-01-01 * \"Shop\"".to_string(),
+                date: NaiveDate::from_ymd_opt(2023, 1, 1),
+                entry_type: EntryType::Transaction,
+                secondary_key: None,
+                line_number: 0,
+            },
+            construct_dated_entry("2022-12-31 open Assets:NewCard", date).unwrap(),
+            closing,
+            construct_dated_entry(
+                "2020-01-01 open Liabilities:OldCard",
+                NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(),
+            )
+            .unwrap(),
+        ];
+        let accounts: Vec<String> = sort_entries(entries, &SortOptions::default())
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.entry_type.section_variant() == EntryType::Account)
+            .map(|e| e.content)
+            .collect();
+        assert_eq!(
+            accounts,
+            [
+                "2020-01-01 open Liabilities:OldCard",
+                "2022-12-31 open Assets:NewCard",
+                "2022-12-31 close Liabilities:OldCard",
+            ]
+        );
+    }
+    #[test]
+    fn test_secondary_key() {
+        assert_eq!(
+            get_secondary_key(" \"Payee\" \"Narration\""),
+            Some("Payee".to_string())
+        );
+        assert_eq!(
+            get_secondary_key(" Assets:Giro   EUR"),
+            Some("Assets:Giro".to_string())
+        );
+        assert_eq!(
+            get_secondary_key(" GME   420.69 EUR"),
+            Some("GME".to_string())
+        );
+        assert_eq!(get_secondary_key(""), None);
+
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let entries = vec![
+            construct_dated_entry("2021-01-01 price USD 0.9 EUR", date).unwrap(),
+            construct_dated_entry("2021-01-01 * \"Zoo\" \"Ticket\"", date).unwrap(),
+            construct_dated_entry("2021-01-01 price GME 420.69 EUR", date).unwrap(),
+            construct_dated_entry("2021-01-01 * \"Bakery\" \"Bread\"", date).unwrap(),
+        ];
+        let contents: Vec<String> = sort_entries(entries, &SortOptions::default())
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.entry_type != EntryType::Section)
+            .map(|e| e.content)
+            .collect();
+        assert_eq!(
+            contents,
+            [
+                "2021-01-01 price GME 420.69 EUR",
+                "2021-01-01 price USD 0.9 EUR",
+                "2021-01-01 * \"Bakery\" \"Bread\"",
+                "2021-01-01 * \"Zoo\" \"Ticket\"",
+            ]
+        );
+    }
+    #[test]
+    fn test_events_section() {
+        let entries = parse_str(
+            "events_section",
+            "2023-06-01 event \"location\" \"Hamburg\"\n\
+             2023-05-02 * \"Bakery\" \"Bread\"\n  Expenses:Food 3.00 EUR\n  Assets:Cash\n\
+             2023-05-01 event \"location\" \"Berlin\"\n\
+             2023-06-02 * \"Bakery\" \"Bread\"\n  Expenses:Food 3.00 EUR\n  Assets:Cash\n",
+        );
+        let sorted_types: Vec<(EntryType, NaiveDate)> =
+            sort_entries(entries, &SortOptions::default())
+                .unwrap()
+                .into_iter()
+                .filter(|e| e.entry_type != EntryType::Section)
+                .map(|e| (e.entry_type, e.date.unwrap()))
+                .collect();
+        assert_eq!(
+            sorted_types,
+            [
+                (
+                    EntryType::Event,
+                    NaiveDate::from_ymd_opt(2023, 5, 1).unwrap()
+                ),
+                (
+                    EntryType::Event,
+                    NaiveDate::from_ymd_opt(2023, 6, 1).unwrap()
+                ),
+                (
+                    EntryType::Transaction,
+                    NaiveDate::from_ymd_opt(2023, 5, 2).unwrap()
+                ),
+                (
+                    EntryType::Transaction,
+                    NaiveDate::from_ymd_opt(2023, 6, 2).unwrap()
+                ),
+            ]
+        );
+    }
+    #[test]
+    fn test_events_same_date_by_type() {
+        let entries = parse_str(
+            "events_same_date_by_type",
+            "2023-05-01 event \"location\" \"Berlin\"\n\
+             2023-05-01 event \"employer\" \"ACME\"\n",
+        );
+        assert_eq!(entries[0].secondary_key, Some("location".to_string()));
+        let events: Vec<Option<String>> = sort_entries(entries, &SortOptions::default())
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.entry_type == EntryType::Event)
+            .map(|e| e.secondary_key)
+            .collect();
+        assert_eq!(
+            events,
+            [Some("employer".to_string()), Some("location".to_string())]
+        );
+    }
+    #[test]
+    fn test_close_entries() {
+        let entries = parse_str(
+            "close_entries",
+            "2023-01-01 open Assets:Giro EUR\n\
+             2022-12-31 close Liabilities:OldCard\n\
+             2024-01-01 close Assets:Giro\n\
+             2020-01-01 open Liabilities:OldCard\n",
+        );
+        let sorted_entries: Vec<(EntryType, String)> =
+            sort_entries(entries, &SortOptions::default())
+                .unwrap()
+                .into_iter()
+                .filter(|e| e.entry_type != EntryType::Section)
+                .map(|e| (e.entry_type, e.content))
+                .collect();
+        assert_eq!(
+            sorted_entries,
+            [
+                (
+                    EntryType::Account,
+                    "2020-01-01 open Liabilities:OldCard".to_string()
+                ),
+                (
+                    EntryType::ClosedAccount,
+                    "2022-12-31 close Liabilities:OldCard".to_string()
+                ),
+                (
+                    EntryType::Account,
+                    "2023-01-01 open Assets:Giro EUR".to_string()
+                ),
+                (
+                    EntryType::ClosedAccount,
+                    "2024-01-01 close Assets:Giro".to_string()
+                ),
+            ]
+        );
+    }
+    #[test]
+    fn test_document_with_metadata() {
+        let entries = parse_str(
+            "document_with_metadata",
+            "2023-03-02 * \"Bakery\" \"Bread\"\n  Expenses:Food 3.00 EUR\n  Assets:Cash\n\
+             2023-03-01 document Assets:Checking \"statements/2023-03.pdf\"\n\
+             \x20 source: \"import\"\n\
+             \x20 checked: TRUE\n",
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].entry_type, EntryType::Document);
+        assert_eq!(
+            entries[1].content,
+            "2023-03-01 document Assets:Checking \"statements/2023-03.pdf\"\n  source: \"import\"\n  checked: TRUE"
+        );
+        let sorted_entries = sort_entries(entries, &SortOptions::default()).unwrap();
+        let heading = sorted_entries
+            .iter()
+            .position(|e| e.content.contains("Documents"))
+            .unwrap();
+        assert_eq!(sorted_entries[heading + 1].entry_type, EntryType::Document);
+    }
+    #[test]
+    fn test_documents_sorted_by_date() {
+        let entries = parse_str(
+            "documents_sorted_by_date",
+            "2023-04-01 document Assets:Checking \"statements/2023-04.pdf\"\n\
+             2023-03-01 custom \"budget\" Expenses:Food \"monthly\" 400.00 EUR\n\
+             2023-03-01 document Assets:Checking \"statements/2023-03.pdf\"\n",
+        );
+        let sorted_entries = sort_entries(entries, &SortOptions::default()).unwrap();
+        let heading = sorted_entries
+            .iter()
+            .position(|e| e.content.contains(&format!("{}Documents{}", DECO, DECO)))
+            .unwrap();
+        assert_eq!(sorted_entries[heading - 1].entry_type, EntryType::Custom);
+        let documents: Vec<NaiveDate> = sorted_entries[heading + 1..heading + 3]
+            .iter()
+            .map(|e| e.date.unwrap())
+            .collect();
+        assert_eq!(
+            documents,
+            [
+                NaiveDate::from_ymd_opt(2023, 3, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2023, 4, 1).unwrap()
+            ]
+        );
+    }
+    #[test]
+    fn test_balance_with_metadata() {
+        let entries = parse_str(
+            "balance_with_metadata",
+            "2023-01-02 balance Assets:Checking 90.00 EUR\n\
+             \x20 statement: \"2023-01.pdf\"\n\
+             2023-01-01 balance Assets:Checking 100.00 EUR\n",
+        );
+        let balances: Vec<String> = sort_entries(entries, &SortOptions::default())
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.entry_type == EntryType::Balance)
+            .map(|e| e.content)
+            .collect();
+        assert_eq!(
+            balances,
+            [
+                "2023-01-01 balance Assets:Checking 100.00 EUR",
+                "2023-01-02 balance Assets:Checking 90.00 EUR\n  statement: \"2023-01.pdf\"",
+            ]
+        );
+    }
+    #[test]
+    fn test_pads_before_balances() {
+        let entries = parse_str(
+            "pads_before_balances",
+            "2023-01-02 balance Assets:Checking 100.00 EUR\n\
+             2023-01-01 pad Assets:Checking Equity:Opening-Balances\n\
+             2023-01-01 balance Assets:Cash 10.00 EUR\n\
+             2023-01-05 balance Assets:Savings 50.00 EUR\n\
+             2023-01-05 balance Assets:Cash 20.00 EUR\n\
+             2023-01-05 pad Assets:Savings Equity:Opening-Balances\n\
+             2023-02-01 pad Assets:Cash Equity:Opening-Balances\n",
+        );
+        let contents: Vec<String> = sort_entries(entries, &SortOptions::default())
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.entry_type.section_variant() == EntryType::Balance)
+            .map(|e| e.content)
+            .collect();
+        assert_eq!(
+            contents,
+            [
+                "2023-01-01 balance Assets:Cash 10.00 EUR",
+                "2023-01-01 pad Assets:Checking Equity:Opening-Balances",
+                "2023-01-02 balance Assets:Checking 100.00 EUR",
+                "2023-01-05 balance Assets:Cash 20.00 EUR",
+                "2023-01-05 pad Assets:Savings Equity:Opening-Balances",
+                "2023-01-05 balance Assets:Savings 50.00 EUR",
+                "2023-02-01 pad Assets:Cash Equity:Opening-Balances",
+            ]
+        );
+    }
+    #[test]
+    fn test_pads_without_account() {
+        let entry = |content: &str, entry_type: EntryType| Entry {
+            content: content.to_string(),
+            date: NaiveDate::from_ymd_opt(2023, 1, 1),
+            entry_type,
+            secondary_key: None,
+            line_number: 0,
+        };
+        let entries = vec![
+            entry("2023-01-01 balance", EntryType::Balance),
+            entry("2023-01-01 pad", EntryType::Pad),
+        ];
+        assert_eq!(move_pads_before_balances(entries.clone()), entries);
+    }
+    #[test]
+    fn test_pad_with_metadata() {
+        let entries = parse_str(
+            "pad_with_metadata",
+            "2023-01-02 balance Assets:Checking 100.00 EUR\n\
+             2022-12-31 open Assets:Checking EUR\n\
+             2023-01-01 pad Assets:Checking Equity:Opening-Balances\n\
+             \x20 note: \"initial balance\"\n",
+        );
+        let pad = entries
+            .iter()
+            .find(|e| e.entry_type == EntryType::Pad)
+            .unwrap();
+        assert_eq!(
+            pad.content,
+            "2023-01-01 pad Assets:Checking Equity:Opening-Balances\n  note: \"initial balance\""
+        );
+        assert_eq!(pad.secondary_key, Some("Assets:Checking".to_string()));
+        let sorted_entries = sort_entries(entries, &SortOptions::default()).unwrap();
+        let i_pad = sorted_entries
+            .iter()
+            .position(|e| e.entry_type == EntryType::Pad)
+            .unwrap();
+        assert_eq!(sorted_entries[i_pad + 1].entry_type, EntryType::Balance);
+    }
+    #[test]
+    fn test_pad_with_orphan_comment() {
+        let entries = parse_str(
+            "pad_with_orphan_comment",
+            "2023-01-02 balance Assets:Checking 100.00 EUR\n\
+             2023-01-01 pad Assets:Checking Equity:Opening-Balances\n\
+             ; from the first statement\n\
+             \n\
+             2023-01-01 balance Assets:Cash 10.00 EUR\n",
+        );
+        let contents: Vec<String> = sort_entries(entries, &SortOptions::default())
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.entry_type != EntryType::Section)
+            .map(|e| e.content)
+            .collect();
+        assert_eq!(
+            contents,
+            [
+                "2023-01-01 balance Assets:Cash 10.00 EUR",
+                "2023-01-01 pad Assets:Checking Equity:Opening-Balances",
+                "; from the first statement",
+                "2023-01-02 balance Assets:Checking 100.00 EUR",
+            ]
+        );
+    }
+    #[test]
+    fn test_includes_keep_their_order() {
+        let entries = parse_str(
+            "includes_keep_their_order",
+            "include \"prices.beancount\"\n\
+             2021-01-01 open Assets:Giro EUR\n\
+             option \"operating_currency\" \"EUR\"\n\
+             include \"accounts.beancount\"\n\
+             include \"2021.beancount\"\n",
+        );
+        let sorted_entries: Vec<(EntryType, String)> =
+            sort_entries(entries, &SortOptions::default())
+                .unwrap()
+                .into_iter()
+                .filter(|e| e.entry_type != EntryType::Section)
+                .map(|e| (e.entry_type, e.content))
+                .collect();
+        assert_eq!(
+            sorted_entries,
+            [
+                (
+                    EntryType::Option,
+                    "option \"operating_currency\" \"EUR\"".to_string()
+                ),
+                (
+                    EntryType::Include,
+                    "include \"prices.beancount\"".to_string()
+                ),
+                (
+                    EntryType::Include,
+                    "include \"accounts.beancount\"".to_string()
+                ),
+                (EntryType::Include, "include \"2021.beancount\"".to_string()),
+                (
+                    EntryType::Account,
+                    "2021-01-01 open Assets:Giro EUR".to_string()
+                ),
+            ]
+        );
+    }
+    #[test]
+    fn test_format_diff() {
+        let original = "b\na\n";
+        let sorted = "a\nb\n";
+        let diff = format_diff(
+            original,
+            sorted,
+            Path::new("./ledger/test.beancount"),
+            DiffFormat::Patch,
+        );
+        assert!(diff.starts_with("--- a/ledger/test.beancount\n+++ b/ledger/test.beancount\n@@"));
+        assert_eq!(
+            format_diff(
+                original,
+                sorted,
+                Path::new("test.beancount"),
+                DiffFormat::Unified
+            ),
+            unified_diff(original, sorted, Path::new("test.beancount"))
+        );
+        assert_eq!(
+            format_diff(
+                original,
+                original,
+                Path::new("test.beancount"),
+                DiffFormat::Patch
+            ),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let original = "2021-01-02 open Assets:Cash\n2021-01-01 open Assets:Giro\n";
+        let sorted = "2021-01-01 open Assets:Giro\n2021-01-02 open Assets:Cash\n";
+        let diff = unified_diff(original, sorted, Path::new("test.beancount"));
+        assert!(diff.starts_with("--- test.beancount\n+++ test.beancount\n@@"));
+        // one line was moved: skip the header and count the removed and added lines
+        let changes: Vec<&str> = diff.lines().skip(3).collect();
+        assert_eq!(changes.iter().filter(|l| l.starts_with('-')).count(), 1);
+        assert_eq!(changes.iter().filter(|l| l.starts_with('+')).count(), 1);
+        assert_eq!(
+            unified_diff(original, original, Path::new("test.beancount")),
+            ""
+        );
+    }
+    #[test]
+    fn test_plugins_after_options() {
+        let entries = parse_str(
+            "plugins_after_options",
+            "plugin \"beancount.plugins.auto_accounts\"\n\
+             option \"title\" \"Finances\"\n\
+             2021-01-01 open Assets:Giro EUR\n\
+             plugin \"beancount.plugins.implicit_prices\"\n\
+             option \"operating_currency\" \"EUR\"\n",
+        );
+        let sorted_entries: Vec<String> = sort_entries(entries, &SortOptions::default())
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.entry_type != EntryType::Section)
+            .map(|e| e.content)
+            .collect();
+        assert_eq!(
+            sorted_entries,
+            [
+                "option \"title\" \"Finances\"",
+                "option \"operating_currency\" \"EUR\"",
+                "plugin \"beancount.plugins.auto_accounts\"",
+                "plugin \"beancount.plugins.implicit_prices\"",
+                "2021-01-01 open Assets:Giro EUR",
+            ]
+        );
+    }
+    #[test]
+    fn test_pushtag_blocks() {
+        let entries = parse_str(
+            "pushtag_blocks",
+            "2023-03-01 * \"Bakery\" \"Bread\"\n  Expenses:Food 3.00 EUR\n  Assets:Cash\n\
+             pushtag #trip-2023\n\
+             2023-02-03 * \"Hotel\" \"Room\"\n  Expenses:Travel 80.00 EUR\n  Assets:Cash\n\
+             2023-02-02 * \"Train\" \"Ticket\"\n  Expenses:Travel 20.00 EUR\n  Assets:Cash\n\
+             poptag #trip-2023\n\
+             2023-01-01 * \"Bakery\" \"Bread\"\n  Expenses:Food 3.00 EUR\n  Assets:Cash\n",
+        );
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].entry_type, EntryType::Block);
+        assert_eq!(
+            entries[1].date.unwrap(),
+            NaiveDate::from_ymd_opt(2023, 2, 2).unwrap()
+        );
+        let sorted_entries: Vec<String> = sort_entries(entries, &SortOptions::default())
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.entry_type != EntryType::Section)
+            .map(|e| e.content.lines().next().unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            sorted_entries,
+            [
+                "2023-01-01 * \"Bakery\" \"Bread\"",
+                "pushtag #trip-2023",
+                "2023-03-01 * \"Bakery\" \"Bread\"",
+            ]
+        );
+    }
+    #[test]
+    fn test_unmatched_poptag() {
+        let path = env::temp_dir().join("beancount-sort_test_unmatched_poptag.beancount");
+        std::fs::write(&path, "2023-01-01 open Assets:Cash\npoptag #trip-2023\n").unwrap();
+        let error = find_entries(read_file(&path).unwrap(), &ParseOptions::default()).unwrap_err();
+        remove_file(&path).unwrap();
+        assert!(error.to_string().starts_with(&format!(
+            "{}:2: poptag #trip-2023 without matching pushtag\n",
+            path.display()
+        )));
+    }
+    #[test]
+    fn test_poptag_out_of_order() {
+        let content = "pushtag #trip\n\
+                       2023-05-02 * \"Hotel\" \"Room\"\n\
+                       pushtag #berlin\n\
+                       2023-05-01 * \"Train\" \"Ticket\"\n\
+                       poptag #trip\n\
+                       2023-05-03 * \"Museum\" \"Ticket\"\n\
+                       poptag #berlin\n\
+                       2023-04-01 * \"Bakery\" \"Bread\"\n";
+        let entries = parse_str("poptag_out_of_order", content);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].entry_type, EntryType::Block);
+        assert_eq!(entries[0].date, NaiveDate::from_ymd_opt(2023, 5, 1));
+        assert_eq!(entries[0].line_number, 1);
+        assert!(entries[0].content.ends_with("poptag #berlin"));
+
+        let path = env::temp_dir().join("beancount-sort_test_unclosed_pushtag.beancount");
+        std::fs::write(&path, "pushtag #a\npushtag #b\npoptag #a\n").unwrap();
+        let error = find_entries(read_file(&path).unwrap(), &ParseOptions::default()).unwrap_err();
+        remove_file(&path).unwrap();
+        assert!(error.to_string().starts_with(&format!(
+            "{}:2: pushtag #b has no matching poptag\n",
+            path.display()
+        )));
+    }
+    #[test]
+    fn test_pushmeta_blocks() {
+        let content = "2023-05-03 * \"Bakery\" \"Bread\"\n  Expenses:Food 3.00 EUR\n  Assets:Cash\n\
+                       pushmeta location: \"Berlin\"\n\
+                       2023-05-01 * \"Hotel\" \"Room\"\n  Expenses:Travel 80.00 EUR\n  Assets:Cash\n\
+                       2023-05-05 * \"Train\" \"Ticket\"\n  Expenses:Travel 20.00 EUR\n  Assets:Cash\n\
+                       popmeta location:\n";
+        let entries = parse_str("pushmeta_blocks", content);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].entry_type, EntryType::Block);
+        assert!(entries[1]
+            .content
+            .starts_with("pushmeta location: \"Berlin\""));
+        assert!(entries[1].content.ends_with("popmeta location:"));
+
+        let path = env::temp_dir().join("beancount-sort_test_strict_meta_blocks.beancount");
+        std::fs::write(&path, content).unwrap();
+        let options = ParseOptions {
+            strict_meta_blocks: true,
+            ..Default::default()
+        };
+        let error = find_entries(read_file(&path).unwrap(), &options).unwrap_err();
+        remove_file(&path).unwrap();
+        assert!(error.to_string().starts_with(
+            "pushmeta location in line 4 can't be kept together because the transaction in line 1"
+        ));
+    }
+    #[test]
+    fn test_parse_section_order() {
+        let (order, missing) = parse_section_order("Prices, Accounts").unwrap();
+        assert_eq!(order[..3], ["Header", "Prices", "Accounts"]);
+        assert_eq!(order.len(), SECTIONS.len());
+        assert!(!missing.contains(&"Header".to_string()));
+        assert!(!missing.contains(&"Prices".to_string()));
+        assert_eq!(missing[0], "Options");
+        assert!(parse_section_order("Prices,Unknown").is_err());
+        assert!(parse_section_order("Prices,Prices").is_err());
+
+        let entries = vec![
+            construct_dated_entry(
+                "2021-01-01 open Assets:Giro",
+                NaiveDate::from_ymd_opt(2021, 1, 1).unwrap(),
+            )
+            .unwrap(),
+            construct_dated_entry(
+                "2021-01-02 price GME 1 EUR",
+                NaiveDate::from_ymd_opt(2021, 1, 2).unwrap(),
+            )
+            .unwrap(),
+        ];
+        let options = SortOptions {
+            section_order: order,
+            ..Default::default()
+        };
+        let sorted_types: Vec<EntryType> = sort_entries(entries, &options)
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.entry_type != EntryType::Section)
+            .map(|e| e.entry_type)
+            .collect();
+        assert_eq!(sorted_types, [EntryType::Price, EntryType::Account]);
+    }
+    #[test]
+    fn test_custom_section() {
+        let content = "2023-02-01 custom \"budget\" Expenses:Food \"monthly\" 400.00 EUR\n\
+                       2023-01-01 event \"location\" \"Berlin\"\n\
+                       2023-01-01 custom \"fava-option\" \"language\" \"de\"\n\
+                       2023-01-01 custom \"budget\" Expenses:Rent \"monthly\" 900.00 EUR\n";
+        let custom_type = |e: &Entry| e.content.split('"').nth(1).unwrap().to_owned();
+        let sorted_entries = sort_entries(
+            parse_str("custom_section_default", content),
+            &SortOptions::default(),
+        )
+        .unwrap();
+        assert!(!sorted_entries.iter().any(|e| e.content.contains("Custom")));
+        let other_entries: Vec<String> = sorted_entries
+            .iter()
+            .filter(|e| e.entry_type.section_variant() == EntryType::OtherEntry)
+            .map(custom_type)
+            .collect();
+        assert_eq!(other_entries, ["budget", "fava-option", "budget"]);
+
+        let options = SortOptions {
+            custom_section: true,
+            ..Default::default()
+        };
+        let sorted_entries =
+            sort_entries(parse_str("custom_section_enabled", content), &options).unwrap();
+        let heading = sorted_entries
+            .iter()
+            .position(|e| e.content.contains(&format!("{}Custom{}", DECO, DECO)))
+            .unwrap();
+        let custom_entries: Vec<(NaiveDate, String)> = sorted_entries[heading + 1..heading + 4]
+            .iter()
+            .map(|e| (e.date.unwrap(), custom_type(e)))
+            .collect();
+        assert_eq!(
+            custom_entries,
+            [
+                (
+                    NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(),
+                    "budget".to_string()
+                ),
+                (
+                    NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(),
+                    "fava-option".to_string()
+                ),
+                (
+                    NaiveDate::from_ymd_opt(2023, 2, 1).unwrap(),
+                    "budget".to_string()
+                ),
+            ]
+        );
+        assert_eq!(sorted_entries[heading + 4].entry_type, EntryType::Section);
+    }
+    #[test]
+    fn test_follow_includes() {
+        let dir = env::temp_dir().join("beancount-sort_test_follow_includes");
+        std::fs::create_dir_all(dir.join("years")).unwrap();
+        std::fs::write(
+            dir.join("main.beancount"),
+            "include \"years/2021.beancount\"\n2021-01-01 open Assets:Giro EUR\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("years/2021.beancount"),
+            "; imported\n2021-02-01 * \"Shop\"\n  Expenses:Food  1 EUR\n  Assets:Giro\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("loop.beancount"), "include \"loop.beancount\"\n").unwrap();
+        let options = ParseOptions {
+            follow_includes: true,
+            ..Default::default()
+        };
+        let entries = find_entries(read_file(&dir.join("main.beancount")).unwrap(), &options)
+            .unwrap()
+            .entries;
+        let circular = find_entries(read_file(&dir.join("loop.beancount")).unwrap(), &options);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].entry_type, EntryType::Transaction);
+        assert!(entries[0].content.starts_with("; imported\n2021-02-01 *"));
+        assert_eq!(entries[1].entry_type, EntryType::Account);
+        assert!(circular
+            .unwrap_err()
+            .to_string()
+            .starts_with("Circular include"));
+    }
+    #[test]
+    fn test_string_open_after() {
+        assert!(!string_open_after(
+            "2023-01-01 * \"Payee\" \"Narration\"",
+            false
+        ));
+        assert!(string_open_after(
+            "2023-01-01 query \"cash\" \"SELECT",
+            false
+        ));
+        assert!(!string_open_after("  FROM year = 2023\"", true));
+        assert!(string_open_after("  WHERE account ~ \\\"Cash\\\"", true));
+        assert!(!string_open_after(
+            "2023-01-01 * \"Payee\" ; it's \"odd",
+            false
+        ));
+    }
+    #[test]
+    fn test_multi_line_narration() {
+        let entries = parse_str(
+            "multi_line_narration",
+            "2023-01-02 * \"Landlord\" \"Rent for January\n\
+             and the garage\"\n\
+             \x20 Expenses:Rent 550.00 EUR\n\
+             \x20 Assets:Checking\n\
+             2023-01-01 * \"Bakery\" \"Bread\"\n\
+             \x20 Expenses:Food 3.00 EUR\n\
+             \x20 Assets:Checking\n",
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].content,
+            "2023-01-02 * \"Landlord\" \"Rent for January\nand the garage\"\n  Expenses:Rent 550.00 EUR\n  Assets:Checking"
+        );
+        let sorted_entries = sort_entries(entries, &SortOptions::default()).unwrap();
+        let transactions: Vec<&str> = sorted_entries
+            .iter()
+            .filter(|e| e.entry_type == EntryType::Transaction)
+            .map(|e| e.content.as_str())
+            .collect();
+        assert!(transactions[0].contains("Bakery"));
+        assert!(transactions[1]
+            .ends_with("and the garage\"\n  Expenses:Rent 550.00 EUR\n  Assets:Checking"));
+    }
+    #[test]
+    fn test_multi_line_query() {
+        let entries = parse_str(
+            "multi_line_query",
+            "2023-01-02 query \"cash\" \"\n\
+             SELECT account, sum(position)\n\
+             WHERE account ~ 'Cash'\n\
+             \"\n\
+             2023-01-01 open Assets:Cash EUR\n",
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].entry_type, EntryType::Query);
+        assert_eq!(
+            entries[0].content,
+            "2023-01-02 query \"cash\" \"\nSELECT account, sum(position)\nWHERE account ~ 'Cash'\n\""
+        );
+        assert_eq!(entries[1].entry_type, EntryType::Account);
+    }
+    #[test]
+    fn test_plugins_with_options() {
+        let entries = parse_str(
+            "plugins_with_options",
+            "option \"title\" \"Finances\"\n\
+             plugin \"beancount.plugins.auto_accounts\"\n\
+             option \"operating_currency\" \"EUR\"\n",
+        );
+        let options = SortOptions {
+            plugins_with_options: true,
+            ..Default::default()
+        };
+        let sorted_entries = sort_entries(entries, &options).unwrap();
+        assert!(!sorted_entries.iter().any(|e| e.content.contains("Plugins")));
+        let heading = sorted_entries
+            .iter()
+            .position(|e| e.content.contains("Options"))
+            .unwrap();
+        let options_section: Vec<EntryType> = sorted_entries[heading + 1..heading + 4]
+            .iter()
+            .map(|e| e.entry_type.clone())
+            .collect();
+        assert_eq!(
+            options_section,
+            [EntryType::Option, EntryType::Plugin, EntryType::Option]
+        );
+    }
+    #[test]
+    fn test_transaction_flags() {
+        let date = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap();
+        for flag in ["*", "!", "txn", "P", "S", "T", "C", "U", "R", "M"] {
+            let line = format!("2023-01-01 {} \"Payee\" \"Narration\"", flag);
+            let entry = construct_dated_entry(&line, date).unwrap();
+            assert_eq!(entry.entry_type, EntryType::Transaction, "flag {}", flag);
+            assert_eq!(entry.secondary_key, Some("Payee".to_string()));
+        }
+        let entry = construct_dated_entry("2023-01-01 X \"Payee\"", date).unwrap();
+        assert_eq!(entry.entry_type, EntryType::OtherEntry);
+    }
+    #[test]
+    fn test_tab_indented_postings() {
+        let entries = parse_str(
+            "tab_indented_postings",
+            "2023-01-01 * \"Bakery\" \"Bread\"\n\
+             \tExpenses:Food 3.00 EUR\n\
+             \x20 Assets:Cash\n\
+             \t  Assets:Giro 0.00 EUR\n",
+        );
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].content,
+            "2023-01-01 * \"Bakery\" \"Bread\"\n\tExpenses:Food 3.00 EUR\n  Assets:Cash\n\t  Assets:Giro 0.00 EUR"
+        );
+    }
+    #[test]
+    fn test_blank_lines_inside_entry() {
+        let entries = parse_str(
+            "blank_lines_inside_entry",
+            "2023-01-02 * \"Bakery\" \"Bread\"\n\
+             \x20 Expenses:Food 3.00 EUR\n\
+             \n\
+             \n\
+             \x20 Assets:Cash\n\
+             \n\
+             \n\
+             2023-01-01 open Assets:Cash EUR\n",
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].content,
+            "2023-01-02 * \"Bakery\" \"Bread\"\n  Expenses:Food 3.00 EUR\n\n  Assets:Cash"
+        );
+        assert_eq!(entries[1].content, "2023-01-01 open Assets:Cash EUR");
+
+        let entries = parse_str(
+            "whitespace_lines_inside_entry",
+            "2023-01-02 * \"Bakery\" \"Bread\"\n\
+             \x20 Expenses:Food 3.00 EUR\n\
+             \x20 \n\
+             \x20 Assets:Cash\n\
+             \x20   receipt: \"bakery.pdf\"\n\
+             \t\n\
+             2023-01-01 open Assets:Cash EUR\n",
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].content,
+            "2023-01-02 * \"Bakery\" \"Bread\"\n  Expenses:Food 3.00 EUR\n\n  Assets:Cash\n    receipt: \"bakery.pdf\""
+        );
+    }
+    #[test]
+    fn test_backup_path() {
+        assert_eq!(
+            backup_path(Path::new("ledger/finances.beancount")),
+            Path::new("ledger/finances_backup.beancount")
+        );
+        assert_eq!(
+            backup_path(Path::new("my_finances_2023.bean")),
+            Path::new("my_finances_2023_backup.bean")
+        );
+        assert_eq!(
+            backup_path(Path::new("ledger/finances")),
+            Path::new("ledger/finances_backup.beancount")
+        );
+        assert_eq!(
+            backup_path(Path::new("finances.2023.beancount")),
+            Path::new("finances.2023_backup.beancount")
+        );
+    }
+
+    #[test]
+    fn test_is_same_file() {
+        let dir = env::temp_dir().join("beancount-sort_test_is_same_file");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("finances.beancount");
+        let other = dir.join("other.beancount");
+        std::fs::write(&path, "").unwrap();
+        std::fs::write(&other, "").unwrap();
+        assert!(is_same_file(&path, &path));
+        assert!(is_same_file(
+            &path,
+            &dir.join(".").join("finances.beancount")
+        ));
+        assert!(!is_same_file(&path, &other));
+        assert!(!is_same_file(&path, &dir.join("missing.beancount")));
+        #[cfg(unix)]
+        {
+            let link = dir.join("link.beancount");
+            std::os::unix::fs::symlink(&path, &link).unwrap();
+            assert!(is_same_file(&path, &link));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Passes the first `limit` bytes to the inner writer and fails after that.
+    struct FailingWriter {
+        inner: Box<dyn Write>,
+        limit: usize,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if buf.len() > self.limit {
+                return Err(io::Error::other("disk full"));
+            }
+            self.limit -= buf.len();
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = env::temp_dir().join("beancount-sort_test_write_atomically");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sorted.beancount");
+        std::fs::write(&path, "2023-01-01 open Assets:Cash\n").unwrap();
+        let ledger_file = LedgerFile {
+            path: PathBuf::from("test.beancount"),
+            file: Input::empty(),
+            entries: parse_str(
+                "write_atomically",
+                "2023-01-02 open Assets:Bank\n2023-01-03 open Assets:Giro\n",
+            ),
+            bom: false,
+            line_ending: LineEnding::Lf,
+            encoding: None,
+        };
+        let error = write_atomically(&path, |out| {
+            let out = FailingWriter {
+                inner: out,
+                limit: 10,
+            };
+            ledger_file.write_ledger_file(Box::new(out), BlankLines::None)
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("disk full"));
+        // the old output is still there and the temporary file is removed
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "2023-01-01 open Assets:Cash\n"
+        );
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        write_atomically(&path, |mut out| Ok(out.write_all(b"replaced\n")?)).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "replaced\n");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomically_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = env::temp_dir().join("beancount-sort_test_keeps_permissions.beancount");
+        std::fs::write(&path, "2023-01-01 open Assets:Cash\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        write_atomically(&path, |mut out| Ok(out.write_all(b"replaced\n")?)).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_dominant_line_ending() {
+        let line_ending = |name: &str, content: &str| {
+            let path = env::temp_dir().join(format!("beancount-sort_test_{}.beancount", name));
+            std::fs::write(&path, content).unwrap();
+            let ledger_file =
+                find_entries(read_file(&path).unwrap(), &ParseOptions::default()).unwrap();
+            remove_file(&path).unwrap();
+            assert!(ledger_file
+                .entries
+                .iter()
+                .all(|e| !e.content.contains('\r')));
+            ledger_file.line_ending
+        };
+        // a single line edited in another editor doesn't change the line ending
+        assert_eq!(
+            line_ending(
+                "mostly_crlf",
+                "2023-01-01 open Assets:Cash\r\n2023-01-02 open Assets:Bank\n2023-01-03 open Assets:Giro\r\n"
+            ),
+            LineEnding::Crlf
+        );
+        assert_eq!(
+            line_ending(
+                "mostly_lf",
+                "2023-01-01 open Assets:Cash\n2023-01-02 open Assets:Bank\r\n2023-01-03 open Assets:Giro\n"
+            ),
+            LineEnding::Lf
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let ledger_file = LedgerFile {
+            path: PathBuf::from("finances.beancount"),
+            file: Input::empty(),
+            entries: parse_str(
+                "serde_round_trip",
+                "option \"title\" \"Finances\"\n\
+                 2023-01-02 * \"Bakery\" \"Bread\"\n\
+                 \x20 Expenses:Food 3.00 EUR\n\
+                 \x20 Assets:Cash\n\
+                 2023-01-02 * \"Butcher\"\n\
+                 \x20 Expenses:Food 9.00 EUR\n\
+                 \x20 Assets:Cash\n\
+                 2023-01-02 balance Assets:Cash 10.00 EUR\n\
+                 2023-01-02 balance Assets:Giro 90.00 EUR\n\
+                 2023-01-01 pad Assets:Giro Equity:Opening-Balances\n",
+            ),
+            bom: true,
+            line_ending: LineEnding::Crlf,
+            encoding: parse_encoding("windows-1252").unwrap(),
+        };
+        let json = serde_json::to_string(&ledger_file).unwrap();
+        let read_back: LedgerFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(read_back.path, ledger_file.path);
+        assert_eq!(read_back.bom, ledger_file.bom);
+        assert_eq!(read_back.line_ending, ledger_file.line_ending);
+        assert_eq!(read_back.encoding, ledger_file.encoding);
+        assert_eq!(read_back.entries, ledger_file.entries);
+        let options = SortOptions::default();
+        assert_eq!(
+            sort_entries(read_back.entries, &options).unwrap(),
+            sort_entries(ledger_file.entries, &options).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_is_written() {
+        let options = SortOptions::default();
+        assert!(is_written("Header", &options));
+        assert!(is_written("Transactions", &options));
+        for section in ["Custom", "Notes", "Queries"] {
+            assert!(!is_written(section, &options));
+        }
+        let options = SortOptions {
+            notes_section: true,
+            interleave_balances: true,
+            ..Default::default()
+        };
+        assert!(is_written("Notes", &options));
+        assert!(!is_written("Balances", &options));
+    }
+
+    #[test]
+    fn test_temp_path() {
+        assert_eq!(
+            temp_path(Path::new("ledger/finances.beancount")),
+            Path::new(&format!(
+                "ledger/finances.beancount.tmp.{}",
+                std::process::id()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_timestamped_backup_path() {
+        let time = Utc.with_ymd_and_hms(2023, 1, 2, 3, 4, 5).unwrap();
+        assert_eq!(
+            timestamped_backup_path(
+                Path::new("ledger/finances.beancount"),
+                Path::new("backups"),
+                &time,
+                0
+            ),
+            Path::new("backups/finances_backup_20230102T030405Z.beancount")
+        );
+        assert_eq!(
+            timestamped_backup_path(Path::new("finances"), Path::new("/tmp/backups"), &time, 2),
+            Path::new("/tmp/backups/finances_backup_20230102T030405Z_2.beancount")
+        );
+    }
+
+    #[test]
+    fn test_backup_file_into_directory() {
+        let dir = env::temp_dir().join("beancount-sort_test_backup_dir");
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = env::temp_dir().join("beancount-sort_test_backup_dir.beancount");
+        std::fs::write(&path, "2023-01-01 open Assets:Cash\n").unwrap();
+        backup_file(&path, Some(&dir.join("nested"))).unwrap();
+        std::fs::write(&path, "2023-01-02 open Assets:Giro\n").unwrap();
+        backup_file(&path, Some(&dir.join("nested"))).unwrap();
+        let mut backups: Vec<String> = std::fs::read_dir(dir.join("nested"))
+            .unwrap()
+            .map(|e| std::fs::read_to_string(e.unwrap().path()).unwrap())
+            .collect();
+        backups.sort();
+        // backups within the same second don't overwrite each other
+        assert_eq!(
+            backups,
+            [
+                "2023-01-01 open Assets:Cash\n",
+                "2023-01-02 open Assets:Giro\n"
+            ]
+        );
+        remove_file(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_indented_comment_inside_transaction() {
+        assert!(matches!(
+            get_line_type("  ; reimbursed by employer", &1).unwrap(),
+            Line::Indent
+        ));
+        assert!(matches!(
+            get_line_type("\t;; reimbursed by employer", &1).unwrap(),
+            Line::Indent
+        ));
+        let entries = parse_str(
+            "indented_comment_inside_transaction",
+            "2023-01-02 * \"Hotel\" \"Room\"\n\
+             \x20 Expenses:Travel 80.00 EUR\n\
+             \x20 ; reimbursed by employer\n\
+             \x20 Assets:Cash\n\
+             2023-01-01 * \"Bakery\" \"Bread\"\n\
+             \x20 Expenses:Food 3.00 EUR\n\
+             \x20 Assets:Cash\n",
+        );
+        let sorted_entries = sort_entries(entries, &SortOptions::default()).unwrap();
+        let hotel = sorted_entries
+            .iter()
+            .find(|e| e.content.contains("Hotel"))
+            .unwrap();
+        assert_eq!(
+            hotel.content,
+            "2023-01-02 * \"Hotel\" \"Room\"\n  Expenses:Travel 80.00 EUR\n  ; reimbursed by employer\n  Assets:Cash"
+        );
+        let bakery = sorted_entries
+            .iter()
+            .find(|e| e.content.contains("Bakery"))
+            .unwrap();
+        assert!(!bakery.content.contains("reimbursed"));
+    }
+
+    #[test]
+    fn test_orphan_comments() {
+        let content = "2023-01-05 * \"Bakery\" \"Bread\"\n\
+                       \x20 Expenses:Food 3.00 EUR\n\
+                       \x20 Assets:Cash\n\
+                       ; checked until here\n\
+                       \n\
+                       2023-01-01 open Assets:Cash\n\
+                       ; the big one\n\
+                       2023-01-02 * \"Landlord\" \"Rent\"\n\
+                       \x20 Expenses:Rent 500.00 EUR\n\
+                       \x20 Assets:Cash\n\
+                       ; end of file\n";
+        let path = env::temp_dir().join("beancount-sort_test_orphan_comments.beancount");
+        std::fs::write(&path, content).unwrap();
+        let parse = |orphan_comments| {
+            let options = ParseOptions {
+                orphan_comments,
+                ..Default::default()
+            };
+            let entries = find_entries(read_file(&path).unwrap(), &options)
+                .unwrap()
+                .entries;
+            sort_entries(entries, &SortOptions::default()).unwrap()
+        };
+
+        // keep: the separated comment stays behind the bakery, the adjacent one above the rent
+        let sorted_entries = parse(OrphanComments::Keep);
+        let contents: Vec<&str> = sorted_entries
+            .iter()
+            .filter(|e| e.entry_type != EntryType::Section)
+            .map(|e| e.content.as_str())
+            .collect();
+        assert_eq!(contents[0], "2023-01-01 open Assets:Cash");
+        assert!(contents[1].starts_with("; the big one\n2023-01-02 * \"Landlord\""));
+        assert_eq!(contents[2], "; end of file");
+        assert!(contents[3].starts_with("2023-01-05 * \"Bakery\""));
+        assert_eq!(contents[4], "; checked until here");
+        assert_eq!(contents.len(), 5);
+
+        // attach-next: the separated comment moves with the next entry
+        let sorted_entries = parse(OrphanComments::AttachNext);
+        let account = sorted_entries
+            .iter()
+            .find(|e| e.entry_type == EntryType::Account)
+            .unwrap();
+        assert_eq!(
+            account.content,
+            "; checked until here\n2023-01-01 open Assets:Cash"
+        );
+
+        // attach-prev: the separated comments become part of the entry in front of them
+        let sorted_entries = parse(OrphanComments::AttachPrev);
+        let bakery = sorted_entries
+            .iter()
+            .find(|e| e.content.contains("Bakery"))
+            .unwrap();
+        assert!(bakery
+            .content
+            .ends_with("  Assets:Cash\n; checked until here"));
+        let rent = sorted_entries
+            .iter()
+            .find(|e| e.content.contains("Landlord"))
+            .unwrap();
+        assert!(rent.content.ends_with("  Assets:Cash\n; end of file"));
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_keep_leading_comments() {
+        let content = "; my ledger\n\
+                       \n\
+                       2023-01-05 * \"Bakery\" \"Bread\"\n\
+                       \x20 Expenses:Food 3.00 EUR\n\
+                       \x20 Assets:Cash\n\
+                       ; only cash from here\n\
+                       option \"operating_currency\" \"EUR\"\n\
+                       ; the big one\n\
+                       2023-01-02 * \"Landlord\" \"Rent\"\n\
+                       \x20 Expenses:Rent 500.00 EUR\n\
+                       \x20 Assets:Cash\n";
+        let options = ParseOptions {
+            keep_leading_comments: true,
+            ..Default::default()
+        };
+        let entries = parse_str_with("keep_leading_comments", content, &options);
+        let sorted_entries = sort_entries(entries, &SortOptions::default()).unwrap();
+        let contents: Vec<&str> = sorted_entries
+            .iter()
+            .filter(|e| e.entry_type != EntryType::Section)
+            .map(|e| e.content.as_str())
+            .collect();
+        assert_eq!(contents[0], "; my ledger");
+        assert_eq!(contents[1], "option \"operating_currency\" \"EUR\"");
+        assert!(contents[2].starts_with("; the big one\n2023-01-02 * \"Landlord\""));
+        assert!(contents[3].starts_with("2023-01-05 * \"Bakery\""));
+        assert!(contents[3].ends_with("  Assets:Cash\n; only cash from here"));
+        assert_eq!(contents.len(), 4);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let entries = parse_str(
+            "find_duplicates",
+            "2023-01-01 open Assets:Cash\n\
+             2023-01-02 * \"Bakery\" \"Bread\"\n\
+             \x20 Expenses:Food 3.00 EUR\n\
+             \x20 Assets:Cash\n\
+             2023-01-03 * \"Bakery\" \"Bread\"\n\
+             \x20 Expenses:Food 3.00 EUR\n\
+             \x20 Assets:Cash\n\
+             2023-01-02 *  \"Bakery\"  \"Bread\"\n\
+             \x20   Expenses:Food   3.00 EUR\n\
+             \x20 Assets:Cash\n",
+        );
+        assert_eq!(entries[1].line_number, 2);
+        assert_eq!(entries[1].last_line_number(), 4);
+        let duplicates = find_duplicates(&entries);
+        assert_eq!(duplicates.len(), 1);
+        let (first, duplicate) = duplicates[0];
+        assert_eq!((first.line_number, first.last_line_number()), (2, 4));
+        assert_eq!(
+            (duplicate.line_number, duplicate.last_line_number()),
+            (8, 10)
+        );
+    }
+
+    #[test]
+    fn test_find_future_entries() {
+        let entries = parse_str(
+            "find_future_entries",
+            "2023-01-01 open Assets:Cash\n\
+             2204-01-02 * \"Bakery\" \"Bread\"\n\
+             \x20 Expenses:Food 3.00 EUR\n\
+             \x20 Assets:Cash\n\
+             \n\
+             ; orphaned comment\n\
+             \n\
+             2023-06-01 price USD 0.90 EUR\n",
+        );
+        let today = NaiveDate::from_ymd_opt(2023, 6, 1).unwrap();
+        let future: Vec<usize> = find_future_entries(&entries, today)
+            .iter()
+            .map(|e| e.line_number)
+            .collect();
+        assert_eq!(future, [2]);
+    }
+
+    #[test]
+    fn test_comments_follow() {
+        let content = "2023-01-05 * \"Bakery\" \"Bread\"\n\
+                       \x20 Expenses:Food 3.00 EUR\n\
+                       \x20 Assets:Cash\n\
+                       ; paid with the last coins\n\
+                       ; need to withdraw money\n\
+                       2023-01-01 open Assets:Cash\n\
+                       \n\
+                       ; the big one\n\
+                       2023-01-02 * \"Landlord\" \"Rent\"\n\
+                       \x20 Expenses:Rent 500.00 EUR\n\
+                       \x20 Assets:Cash\n";
+        let path = env::temp_dir().join("beancount-sort_test_comments_follow.beancount");
+        std::fs::write(&path, content).unwrap();
+        let options = ParseOptions {
+            comments_follow: true,
+            ..Default::default()
+        };
+        let entries = find_entries(read_file(&path).unwrap(), &options)
+            .unwrap()
+            .entries;
+        remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(entries[0]
+            .content
+            .ends_with("  Assets:Cash\n; paid with the last coins\n; need to withdraw money"));
+        assert_eq!(entries[1].content, "2023-01-01 open Assets:Cash");
+        assert!(entries[2].content.starts_with("; the big one\n2023-01-02"));
+    }
+
+    #[test]
+    fn test_tiebreak() {
+        let entries = parse_str(
+            "tiebreak",
+            "2023-01-01 * \"Zoo\" \"Tickets\"\n\
+             \x20 Expenses:Fun 30.00 EUR\n\
+             \x20 Assets:Cash\n\
+             2023-01-01 * \"Bakery\" \"Bread\"\n\
+             \x20 Expenses:Food 3.00 EUR\n\
+             \x20 Assets:Cash\n\
+             2023-01-01 * \"Coffee\"\n\
+             \x20 Expenses:Food 1,200.50 EUR\n\
+             \x20 Assets:Cash\n\
+             2023-01-01 price USD 0.93 EUR\n\
+             2023-01-01 price CHF 1.01 EUR\n",
+        );
+        let order = |tiebreak| {
+            let options = SortOptions {
+                tiebreak,
+                ..Default::default()
+            };
+            sort_entries(entries.clone(), &options)
+                .unwrap()
+                .into_iter()
+                .filter(|e| matches!(e.entry_type, EntryType::Transaction | EntryType::Price))
+                .map(|e| e.content.lines().next().unwrap().to_owned())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            order(TieBreak::Date),
+            [
+                "2023-01-01 price CHF 1.01 EUR",
+                "2023-01-01 price USD 0.93 EUR",
+                "2023-01-01 * \"Bakery\" \"Bread\"",
+                "2023-01-01 * \"Coffee\"",
+                "2023-01-01 * \"Zoo\" \"Tickets\"",
+            ]
+        );
+        assert_eq!(
+            order(TieBreak::Payee)[2..],
+            [
+                "2023-01-01 * \"Coffee\"",
+                "2023-01-01 * \"Bakery\" \"Bread\"",
+                "2023-01-01 * \"Zoo\" \"Tickets\"",
+            ]
+        );
+        assert_eq!(
+            order(TieBreak::Narration)[2..],
+            [
+                "2023-01-01 * \"Bakery\" \"Bread\"",
+                "2023-01-01 * \"Coffee\"",
+                "2023-01-01 * \"Zoo\" \"Tickets\"",
+            ]
+        );
+        assert_eq!(
+            order(TieBreak::Amount),
+            [
+                "2023-01-01 price USD 0.93 EUR",
+                "2023-01-01 price CHF 1.01 EUR",
+                "2023-01-01 * \"Bakery\" \"Bread\"",
+                "2023-01-01 * \"Zoo\" \"Tickets\"",
+                "2023-01-01 * \"Coffee\"",
+            ]
+        );
+        assert_eq!(
+            order(TieBreak::Stable),
+            [
+                "2023-01-01 price USD 0.93 EUR",
+                "2023-01-01 price CHF 1.01 EUR",
+                "2023-01-01 * \"Zoo\" \"Tickets\"",
+                "2023-01-01 * \"Bakery\" \"Bread\"",
+                "2023-01-01 * \"Coffee\"",
+            ]
+        );
+        assert_eq!(order("input".parse().unwrap()), order(TieBreak::Stable));
+    }
+
+    #[test]
+    fn test_invalid_dates() {
+        let error = get_line_type("2021-02-31 * \"Shop\"", &3).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("2021-02-31"));
+        assert!(message.contains("line 3"));
+        assert!(message.contains("\"2021-02-31 * \"Shop\"\""));
+        assert!(get_line_type("2021-19-07 open Assets:Cash", &1).is_err());
+
+        let content = "2021-01-01 open Assets:Cash\n\
+                       2021-02-31 * \"Shop\"\n\
+                       2021-01-02 * \"Bakery\"\n\
+                       \x20 Expenses:Food 3.00 EUR\n\
+                       \x20 Assets:Cash\n";
+        let path = env::temp_dir().join("beancount-sort_test_invalid_dates.beancount");
+        std::fs::write(&path, content).unwrap();
+        assert!(find_entries(read_file(&path).unwrap(), &ParseOptions::default()).is_err());
+        let options = ParseOptions {
+            lenient_dates: true,
+            ..Default::default()
+        };
+        let entries = find_entries(read_file(&path).unwrap(), &options)
+            .unwrap()
+            .entries;
+        remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].content, "2021-02-31 * \"Shop\"");
+        assert_eq!(entries[1].entry_type, EntryType::OtherEntry);
+
+        // the postings stay with the transaction at the start of the file and after another entry
+        let transaction = "2021-02-31 * \"Shop\"\n  Expenses:Food 3.00 EUR\n  Assets:Cash";
+        let entries = parse_str_with(
+            "invalid_dates_first",
+            &format!("{}\n2021-01-01 open Assets:Cash\n", transaction),
+            &options,
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].content, transaction);
+        assert_eq!(entries[0].entry_type, EntryType::OtherEntry);
+        let entries = parse_str_with(
+            "invalid_dates_after",
+            &format!("2021-01-01 open Assets:Cash\n{}\n", transaction),
+            &options,
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].content, "2021-01-01 open Assets:Cash");
+        assert_eq!(entries[1].content, transaction);
+        let sorted_entries = sort_entries(entries, &SortOptions::default()).unwrap();
+        let other_entries = sorted_entries
+            .iter()
+            .position(|e| e.content.contains("Other Entries"))
+            .unwrap();
+        assert_eq!(sorted_entries[other_entries + 1].content, transaction);
+    }
+
+    #[test]
+    fn test_notes_section() {
+        let content = "2022-01-02 note Assets:Checking \"Wire received\"\n\
+                       \x20 source: \"bank statement\"\n\
+                       2022-01-01 event \"location\" \"Berlin\"\n\
+                       2022-01-01 note Assets:Checking \"Account opened\"\n";
+        let entries = parse_str("notes_section", content);
+        assert_eq!(entries[0].entry_type, EntryType::Note);
+        assert_eq!(
+            entries[0].content,
+            "2022-01-02 note Assets:Checking \"Wire received\"\n  source: \"bank statement\""
+        );
+        assert_eq!(
+            entries[0].secondary_key,
+            Some("Assets:Checking".to_string())
+        );
+
+        let sorted_entries = sort_entries(entries.clone(), &SortOptions::default()).unwrap();
+        assert!(!sorted_entries
+            .iter()
+            .any(|e| e.content.contains(&format!("{}Notes{}", DECO, DECO))));
+        let other_heading = sorted_entries
+            .iter()
+            .position(|e| e.content.contains("Other Entries"))
+            .unwrap();
+        assert_eq!(
+            sorted_entries[other_heading + 1].date.unwrap(),
+            NaiveDate::from_ymd_opt(2022, 1, 1).unwrap()
+        );
+        assert_eq!(
+            sorted_entries[other_heading + 2].entry_type,
+            EntryType::Note
+        );
+
+        let options = SortOptions {
+            notes_section: true,
+            ..Default::default()
+        };
+        let sorted_entries = sort_entries(entries, &options).unwrap();
+        let heading = sorted_entries
+            .iter()
+            .position(|e| e.content.contains(&format!("{}Notes{}", DECO, DECO)))
+            .unwrap();
+        let notes: Vec<&str> = sorted_entries[heading + 1..heading + 3]
+            .iter()
+            .map(|e| e.content.as_str())
+            .collect();
+        assert!(notes[0].contains("Account opened"));
+        assert!(notes[1].contains("Wire received"));
+    }
+
+    #[test]
+    fn test_queries_section() {
+        let content = "2023-02-01 query \"cash\" \"SELECT account WHERE account ~ 'Cash'\"\n\
+                       2023-01-01 event \"location\" \"Berlin\"\n\
+                       2023-01-01 query \"food\" \"SELECT sum(position) WHERE account ~ 'Food'\"\n";
+        let sorted_entries = sort_entries(
+            parse_str("queries_section", content),
+            &SortOptions::default(),
+        )
+        .unwrap();
+        assert!(!sorted_entries
+            .iter()
+            .any(|e| e.content.contains(&format!("{}Queries{}", DECO, DECO))));
+        assert_eq!(
+            sorted_entries
+                .iter()
+                .filter(|e| e.entry_type == EntryType::Query)
+                .count(),
+            2
+        );
+
+        let options = SortOptions {
+            queries_section: true,
+            always_show_sections: true,
+            ..Default::default()
+        };
+        let sorted_entries =
+            sort_entries(parse_str("queries_section_enabled", content), &options).unwrap();
+        let heading = sorted_entries
+            .iter()
+            .position(|e| e.content.contains(&format!("{}Queries{}", DECO, DECO)))
+            .unwrap();
+        let queries: Vec<Option<String>> = sorted_entries[heading + 1..heading + 3]
+            .iter()
+            .map(|e| e.secondary_key.clone())
+            .collect();
+        assert_eq!(
+            queries,
+            [Some("food".to_string()), Some("cash".to_string())]
+        );
+        assert!(sorted_entries[heading - 1]
+            .content
+            .contains(&format!("{}Other Entries{}", DECO, DECO)));
+    }
+
+    #[test]
+    fn test_org_headings() {
+        assert!(matches!(
+            get_line_type("* Expenses", &1).unwrap(),
+            Line::OrgHeading
+        ));
+        assert!(matches!(get_line_type("**", &1).unwrap(), Line::OrgHeading));
+        assert!(matches!(
+            get_line_type("2023-01-01 * \"Shop\"", &1).unwrap(),
+            Line::Date(_)
+        ));
+        assert!(get_line_type("*Expenses", &1).is_err());
+
+        let content = "* Accounts\n\
+                       2023-01-01 open Assets:Cash\n\
+                       ** Food\n\
+                       2023-01-02 * \"Bakery\"\n\
+                       \x20 Expenses:Food 3.00 EUR\n\
+                       \x20 Assets:Cash\n";
+        let entries = parse_str("org_headings", content);
+        assert_eq!(entries.len(), 2);
+        assert!(!entries.iter().any(|e| e.content.contains("Food\n")));
+
+        let path = env::temp_dir().join("beancount-sort_test_keep_org_headings.beancount");
+        std::fs::write(&path, content).unwrap();
+        let options = ParseOptions {
+            keep_org_headings: true,
+            ..Default::default()
+        };
+        let entries = find_entries(read_file(&path).unwrap(), &options)
+            .unwrap()
+            .entries;
+        remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].content,
+            "* Accounts\n2023-01-01 open Assets:Cash"
+        );
+        assert!(entries[1].content.starts_with("** Food\n2023-01-02 *"));
+    }
+
+    #[test]
+    fn test_lenient() {
+        let content = "2023-01-01 open Assets:Cash\n\
+                       this is not beancount\n\
+                       2023-01-02 * \"Bakery\"\n\
+                       \x20 Expenses:Food 3.00 EUR\n\
+                       \x20 Assets:Cash\n";
+        let path = env::temp_dir().join("beancount-sort_test_lenient.beancount");
+        std::fs::write(&path, content).unwrap();
+        let error = find_entries(read_file(&path).unwrap(), &ParseOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("this is not beancount"));
+        let options = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let entries = find_entries(read_file(&path).unwrap(), &options)
+            .unwrap()
+            .entries;
+        remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].entry_type, EntryType::OtherEntry);
+        assert_eq!(entries[1].content, "this is not beancount");
+        assert_eq!(entries[1].line_number, 2);
+        assert_eq!(entries[2].entry_type, EntryType::Transaction);
+    }
+
+    #[test]
+    fn test_filter_sections() {
+        let content = "2023-01-01 open Assets:Cash\n\
+                       2023-01-02 price USD 0.93 EUR\n\
+                       2023-01-02 * \"Bakery\"\n\
+                       \x20 Expenses:Food 3.00 EUR\n\
+                       \x20 Assets:Cash\n";
+        let options = SortOptions {
+            filter_sections: vec!["Transactions".to_string(), "Prices".to_string()],
+            ..Default::default()
+        };
+        let sorted_entries = sort_entries(parse_str("filter_sections", content), &options).unwrap();
+        let types: Vec<EntryType> = sorted_entries
+            .iter()
+            .map(|e| e.entry_type.clone())
+            .collect();
+        assert_eq!(
+            types,
+            [
+                EntryType::Section,
+                EntryType::Price,
+                EntryType::Section,
+                EntryType::Transaction
+            ]
+        );
+        let options = SortOptions {
+            filter_sections: vec!["Transaktionen".to_string()],
+            ..Default::default()
+        };
+        assert!(sort_entries(parse_str("filter_sections_unknown", content), &options).is_err());
+    }
+
+    #[test]
+    fn test_error_location() {
+        let content = "2023-01-01 open Assets:Cash\n\
+                       2023-01-02 * \"Bakery\"\n\
+                       \x20 Expenses:Food 3.00 EUR\n\
+                       \x20 Assets:Cash\n\
+                       this is not beancount\n\
+                       2023-01-03 open Assets:Bank\n";
+        let path = env::temp_dir().join("beancount-sort_test_error_location.beancount");
+        std::fs::write(&path, content).unwrap();
+        let error = find_entries(read_file(&path).unwrap(), &ParseOptions::default())
+            .unwrap_err()
+            .to_string();
+        let lines: Vec<&str> = error.lines().collect();
+        assert!(lines[0].starts_with(&format!("{}:5: ", path.display())));
+        assert!(lines[0].contains("this is not beancount"));
+        assert_eq!(
+            lines[1..],
+            [
+                "  3 |   Expenses:Food 3.00 EUR",
+                "  4 |   Assets:Cash",
+                "> 5 | this is not beancount",
+                "  6 | 2023-01-03 open Assets:Bank",
+            ]
+        );
+
+        std::fs::write(&path, "option \"title\" \"Finances\"\n  name: \"Cash\"\n").unwrap();
+        let error = find_entries(read_file(&path).unwrap(), &ParseOptions::default())
+            .unwrap_err()
+            .to_string();
+        remove_file(&path).unwrap();
+        assert!(error.starts_with(&format!("{}:2: Misplaced indented line", path.display())));
+        assert!(error.ends_with("  1 | option \"title\" \"Finances\"\n> 2 |   name: \"Cash\""));
+    }
+
+    #[test]
+    fn test_missing_space_after_date() {
+        let path = env::temp_dir().join("beancount-sort_test_missing_space_after_date.beancount");
+        std::fs::write(
+            &path,
+            "2023-01-01 open Assets:Cash\n;2023-01-05 see email\n2023-01-05see notes\n",
+        )
+        .unwrap();
+        let error = find_entries(read_file(&path).unwrap(), &ParseOptions::default())
+            .unwrap_err()
+            .to_string();
+        remove_file(&path).unwrap();
+        assert!(error.starts_with(&format!(
+            "{}:3: Couldn't find the entry type of \"2023-01-05see notes\". Did you forget a space after the date?",
+            path.display()
+        )));
+        assert!(error.ends_with("> 3 | 2023-01-05see notes"));
+        let error =
+            construct_dated_entry("2023-01-05 ", NaiveDate::from_ymd_opt(2023, 1, 5).unwrap())
+                .unwrap_err()
+                .to_string();
+        assert!(!error.contains("forget a space"));
+    }
+
+    #[test]
+    fn test_split_by_date_range() {
+        let entries = parse_str(
+            "split_by_date_range",
+            "option \"title\" \"Finances\"\n\
+             2022-12-31 open Assets:Cash\n\
+             ; still in 2022\n\
+             \n\
+             2023-01-01 * \"Bakery\"\n\
+             \x20 Expenses:Food 3.00 EUR\n\
+             \x20 Assets:Cash\n\
+             2024-01-01 price USD 0.90 EUR\n",
+        );
+        let from = Some(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap());
+        let to = Some(NaiveDate::from_ymd_opt(2023, 12, 31).unwrap());
+        let (in_range, out_of_range) = split_by_date_range(entries.clone(), from, to);
+        let types = |entries: &[Entry]| -> Vec<EntryType> {
+            entries.iter().map(|e| e.entry_type.clone()).collect()
+        };
+        assert_eq!(
+            types(&in_range),
+            [EntryType::Option, EntryType::Transaction]
+        );
+        assert_eq!(
+            types(&out_of_range),
+            [EntryType::Account, EntryType::Comment, EntryType::Price]
+        );
+        let (in_range, out_of_range) = split_by_date_range(entries, None, to);
+        assert_eq!(in_range.len(), 4);
+        assert_eq!(types(&out_of_range), [EntryType::Price]);
+    }
+
+    #[test]
+    fn test_error_line_numbers() {
+        let path = env::temp_dir().join("beancount-sort_test_error_line_numbers.beancount");
+        let error_line = |content: &str, skipn: usize| {
+            std::fs::write(&path, content).unwrap();
+            let options = ParseOptions {
+                skipn,
+                ..Default::default()
+            };
+            let error = find_entries(read_file(&path).unwrap(), &options)
+                .unwrap_err()
+                .to_string();
+            let prefix = format!("{}:", path.display());
+            error
+                .strip_prefix(&prefix)
+                .unwrap()
+                .split(':')
+                .next()
+                .unwrap()
+                .parse::<usize>()
+                .unwrap()
+        };
+        assert_eq!(error_line("oops\n2023-01-01 open Assets:Cash\n", 0), 1);
+        assert_eq!(
+            error_line(
+                "2023-01-01 open Assets:Cash\n\noops\n\n2023-01-02 open Assets:Bank\n",
+                0
+            ),
+            3
+        );
+        assert_eq!(error_line("2023-01-01 open Assets:Cash\n\n\noops", 0), 4);
+        assert_eq!(
+            error_line(
+                ";; -*- mode: beancount -*-\n\n\n2023-01-01 open Assets:Cash\noops\n",
+                2
+            ),
+            5
+        );
+        assert_eq!(
+            error_line(
+                "2023-01-01 query \"q\" \"\nSELECT *\n\"\noption \"title\" \"x\"\n  Assets:Cash\n",
+                0
+            ),
+            5
+        );
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_metadata_under_all_directives() {
+        let entries = parse_str(
+            "metadata_under_all_directives",
+            "2019-01-01 open Assets:Broker USD\n\
+             \x20 description: \"Interactive Brokers\"\n\
+             2019-01-02 price USD 0.90 EUR\n\
+             \x20 source: \"ECB\"\n\
+             2019-01-03 note Assets:Broker \"Account opened\"\n\
+             \x20 ticket: 42\n\
+             2019-01-04 event \"location\" \"Berlin\"\n\
+             \x20 reason: \"moved\"\n",
+        );
+        let summary: Vec<(EntryType, usize)> = entries
+            .iter()
+            .map(|e| (e.entry_type.clone(), e.content.lines().count()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (EntryType::Account, 2),
+                (EntryType::Price, 2),
+                (EntryType::Note, 2),
+                (EntryType::Event, 2)
+            ]
+        );
+        assert_eq!(
+            entries[0].content,
+            "2019-01-01 open Assets:Broker USD\n  description: \"Interactive Brokers\""
+        );
+    }
+
+    #[test]
+    fn test_options_keep_their_order() {
+        let entries = parse_str(
+            "options_keep_their_order",
+            "option \"title\" \"Finances\"\n\
+             2023-01-01 open Assets:Cash\n\
+             option \"operating_currency\" \"EUR\"\n\
+             option \"documents\" \"/home/user/documents/finances/\n\
+             statements\"\n\
+             option \"booking_method\" \"FIFO\"\n\
+             option \"operating_currency\" \"CHF\"\n",
+        );
+        assert_eq!(entries.len(), 6);
+        assert_eq!(
+            entries[3].content,
+            "option \"documents\" \"/home/user/documents/finances/\nstatements\""
+        );
+        for tiebreak in [
+            TieBreak::Date,
+            TieBreak::Payee,
+            TieBreak::Narration,
+            TieBreak::Amount,
+            TieBreak::Stable,
+        ] {
+            let options = SortOptions {
+                tiebreak,
+                ..Default::default()
+            };
+            let sorted_options: Vec<String> = sort_entries(entries.clone(), &options)
+                .unwrap()
+                .into_iter()
+                .filter(|e| e.entry_type == EntryType::Option)
+                .map(|e| e.content.split('"').nth(1).unwrap().to_owned())
+                .collect();
+            assert_eq!(
+                sorted_options,
+                [
+                    "title",
+                    "operating_currency",
+                    "documents",
+                    "booking_method",
+                    "operating_currency"
+                ]
+            );
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_format_json() {
+        let path = env::temp_dir().join("beancount-sort_test_format_json.beancount");
+        std::fs::write(
+            &path,
+            "2023-01-02 * \"Bakery\"\n  Expenses:Food 3.00 EUR\n  Assets:Cash\n2023-01-01 open Assets:Cash\n",
+        )
+        .unwrap();
+        let mut ledger_file =
+            find_entries(read_file(&path).unwrap(), &ParseOptions::default()).unwrap();
+        remove_file(&path).unwrap();
+        ledger_file.entries = sort_entries(ledger_file.entries, &SortOptions::default()).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&ledger_file.format_json().unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {
+                    "content": "2023-01-01 open Assets:Cash",
+                    "date": "2023-01-01",
+                    "entry_type": "Account"
+                },
+                {
+                    "content": "2023-01-02 * \"Bakery\"\n  Expenses:Food 3.00 EUR\n  Assets:Cash",
+                    "date": "2023-01-02",
+                    "entry_type": "Transaction"
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_get_account_name() {
+        assert_eq!(
+            get_account_name("2023-01-01 open Assets:Cash EUR"),
+            Some("Assets:Cash")
+        );
+        assert_eq!(
+            get_account_name("2023-01-01 open Assets:Bank:Girokonto-2 EUR,USD"),
+            Some("Assets:Bank:Girokonto-2")
+        );
+        assert_eq!(
+            get_account_name("2023-01-01 open Assets:2023:Depot"),
+            Some("Assets:2023:Depot")
+        );
+        assert_eq!(
+            get_account_name("2023-01-01 close Expenses:Café:Bäckerei ; closed"),
+            Some("Expenses:Café:Bäckerei")
+        );
+        assert_eq!(
+            get_account_name("; note\n2023-01-01 open Ausgaben:Öffi\n  name: \"Bus\""),
+            Some("Ausgaben:Öffi")
+        );
+        assert_eq!(get_account_name("2023-01-01 open Assets"), None);
+        assert_eq!(get_account_name("2023-01-01 * \"Assets:Cash\""), None);
+    }
+
+    #[test]
+    fn test_accounts_order() {
+        let entries = parse_str(
+            "accounts_order",
+            "2023-01-01 open Liabilities:Card\n\
+             2023-01-01 open Assets:Cash\n\
+             2023-12-31 close Assets:Bank\n\
+             2022-01-01 open Assets:Bank\n\
+             \n\
+             ; the old bank account\n\
+             \n\
+             2023-01-01 open Expenses:Food\n",
+        );
+        let order = |accounts_order| {
+            let options = SortOptions {
+                accounts_order,
+                ..Default::default()
+            };
+            sort_entries(entries.clone(), &options)
+                .unwrap()
+                .into_iter()
+                .filter(|e| {
+                    matches!(
+                        e.entry_type,
+                        EntryType::Account | EntryType::ClosedAccount | EntryType::Comment
+                    )
+                })
+                .map(|e| e.content)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            order(AccountsOrder::Date),
+            [
+                "2022-01-01 open Assets:Bank",
+                "; the old bank account",
+                "2023-01-01 open Assets:Cash",
+                "2023-01-01 open Expenses:Food",
+                "2023-01-01 open Liabilities:Card",
+                "2023-12-31 close Assets:Bank",
+            ]
+        );
+        assert_eq!(
+            order(AccountsOrder::Name),
+            [
+                "2022-01-01 open Assets:Bank",
+                "; the old bank account",
+                "2023-12-31 close Assets:Bank",
+                "2023-01-01 open Assets:Cash",
+                "2023-01-01 open Expenses:Food",
+                "2023-01-01 open Liabilities:Card",
+            ]
+        );
+        assert_eq!(
+            "name".parse::<AccountsOrder>().unwrap(),
+            AccountsOrder::Name
+        );
+        assert!("size".parse::<AccountsOrder>().is_err());
+    }
+
+    #[test]
+    fn test_commodities_order() {
+        assert_eq!(
+            get_commodity_symbol("1970-01-01 commodity VANGUARD.FTSE\n  name: \"FTSE\""),
+            Some("VANGUARD.FTSE")
+        );
+        assert_eq!(get_commodity_symbol("1970-01-01 open Assets:Cash"), None);
+        let entries = parse_str(
+            "commodities_order",
+            "1970-01-01 commodity USD\n\
+             \x20 name: \"US Dollar\"\n\
+             1970-01-01 commodity EUR\n\
+             \x20 name: \"Euro\"\n\
+             2021-01-01 commodity GME\n\
+             1970-01-01 commodity CHF\n",
+        );
+        let options = SortOptions {
+            commodities_order: CommoditiesOrder::Symbol,
+            ..Default::default()
+        };
+        let symbols: Vec<String> = sort_entries(entries, &options)
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.entry_type == EntryType::Commodity)
+            .map(|e| e.content)
+            .collect();
+        assert_eq!(
+            symbols,
+            [
+                "1970-01-01 commodity CHF",
+                "1970-01-01 commodity EUR\n  name: \"Euro\"",
+                "2021-01-01 commodity GME",
+                "1970-01-01 commodity USD\n  name: \"US Dollar\"",
+            ]
+        );
+        assert!("name".parse::<CommoditiesOrder>().is_err());
+    }
+
+    #[test]
+    fn test_stats() {
+        let entries = parse_str(
+            "stats",
+            "option \"title\" \"Finances\"\n\
+             2021-01-01 open Assets:Cash\n\
+             ; a comment\n\
+             2021-03-01 close Assets:Cash\n\
+             2020-01-01 open Assets:Bank\n\
+             2022-05-01 * \"Bakery\"\n\
+             \x20 Expenses:Food 3.00 EUR\n\
+             \x20 Assets:Bank\n\
+             2021-02-01 note Assets:Bank \"Moved\"\n",
+        );
+        let options = SortOptions::default();
+        let sorted = sort_entries(entries.clone(), &options).unwrap();
+        let counts = count_sections(&sorted, &options).unwrap();
+        assert_eq!(counts.len(), 12);
+        let count = |section: &str| counts.iter().find(|(s, _)| s == section).unwrap().1;
+        assert_eq!(count("Header"), 0);
+        assert_eq!(count("Options"), 1);
+        assert_eq!(count("Accounts"), 3);
+        assert_eq!(count("Other Entries"), 1);
+        assert_eq!(count("Transactions"), 1);
+        assert_eq!(count("Prices"), 0);
+        assert_eq!(
+            date_range(&sorted),
+            Some((
+                NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2022, 5, 1).unwrap()
+            ))
+        );
+        assert_eq!(date_range(&entries[..1]), None);
+        let options = SortOptions {
+            notes_section: true,
+            ..Default::default()
+        };
+        let counts = count_sections(&entries, &options).unwrap();
+        assert!(counts.contains(&("Notes".to_owned(), 1)));
+        assert!(counts.contains(&("Other Entries".to_owned(), 0)));
+    }
+
+    #[test]
+    fn test_prices_order() {
+        assert_eq!(
+            get_price_commodity("2021-01-21 price GME                420.69 EUR"),
+            Some("GME")
+        );
+        assert_eq!(
+            get_price_commodity("2021-01-21 price VANGUARD.FTSE 80 GBP"),
+            Some("VANGUARD.FTSE")
+        );
+        assert_eq!(get_price_commodity("2021-01-21 * \"price GME\""), None);
+        let entries = parse_str(
+            "prices_order",
+            "2021-01-02 price USD 0.91 EUR\n\
+             2021-01-01 price USD 0.90 EUR\n\
+             2021-01-02 price CHF 1.02 EUR\n\
+             2021-01-03 price CHF 1.03 EUR\n\
+             2021-01-01 price CHF 1.01 EUR\n\
+             2021-01-03 price USD 0.92 EUR\n",
+        );
+        let options = SortOptions {
+            prices_order: PricesOrder::Commodity,
+            ..Default::default()
+        };
+        let prices: Vec<String> = sort_entries(entries, &options)
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.entry_type == EntryType::Price)
+            .map(|e| e.content)
+            .collect();
+        assert_eq!(
+            prices,
+            [
+                "2021-01-01 price CHF 1.01 EUR",
+                "2021-01-02 price CHF 1.02 EUR",
+                "2021-01-03 price CHF 1.03 EUR",
+                "2021-01-01 price USD 0.90 EUR",
+                "2021-01-02 price USD 0.91 EUR",
+                "2021-01-03 price USD 0.92 EUR",
+            ]
+        );
+        assert_eq!(
+            "commodity".parse::<PricesOrder>().unwrap(),
+            PricesOrder::Commodity
+        );
+    }
+
+    #[test]
+    fn test_reverse() {
+        let entries = parse_str(
+            "reverse",
+            "2021-01-02 open Assets:Bank\n\
+             2021-01-01 open Assets:Cash\n\
+             2021-01-01 price USD 0.90 EUR\n\
+             2021-01-02 price USD 0.91 EUR\n\
+             2021-01-01 * \"Bakery\"\n\
+             2021-01-03 * \"Zoo\"\n\
+             2021-01-02 * \"Coffee\"\n",
+        );
+        let options = SortOptions {
+            reverse: true,
+            ..Default::default()
+        };
+        let dates = |entry_type: EntryType, entries: &[Entry]| -> Vec<u32> {
+            entries
+                .iter()
+                .filter(|e| e.entry_type == entry_type)
+                .map(|e| e.date.unwrap().day())
+                .collect()
+        };
+        let sorted = sort_entries(entries, &options).unwrap();
+        assert_eq!(dates(EntryType::Account, &sorted), [1, 2]);
+        assert_eq!(dates(EntryType::Price, &sorted), [2, 1]);
+        assert_eq!(dates(EntryType::Transaction, &sorted), [3, 2, 1]);
+    }
+
+    #[test]
+    fn test_interleave_balances() {
+        let entries = parse_str(
+            "interleave_balances",
+            "2021-01-02 * \"Bakery\"\n\
+             \x20 Expenses:Food 3.00 EUR\n\
+             \x20 Assets:Cash\n\
+             2021-01-02 balance Assets:Cash 10.00 EUR\n\
+             2021-01-01 pad Assets:Cash Equity:Opening-Balances\n\
+             2021-01-01 * \"Zoo\"\n\
+             2021-01-03 balance Assets:Cash 7.00 EUR\n",
+        );
+        let options = SortOptions {
+            interleave_balances: true,
+            ..Default::default()
+        };
+        let sorted = sort_entries(entries, &options).unwrap();
+        let first_lines: Vec<&str> = sorted
+            .iter()
+            .filter(|e| e.entry_type != EntryType::Section)
+            .map(|e| e.content.lines().next().unwrap())
+            .collect();
+        assert_eq!(
+            first_lines,
+            [
+                "2021-01-01 * \"Zoo\"",
+                // a pad stays in front of the balance it pads
+                "2021-01-01 pad Assets:Cash Equity:Opening-Balances",
+                "2021-01-02 balance Assets:Cash 10.00 EUR",
+                "2021-01-02 * \"Bakery\"",
+                "2021-01-03 balance Assets:Cash 7.00 EUR",
+            ]
+        );
+        let deco = DECO.repeat(NDECO);
+        assert!(!sorted
+            .iter()
+            .any(|e| e.content.contains(&format!("{}Balances{}", deco, deco))));
+    }
+
+    #[test]
+    fn test_undated_entries_come_first() {
+        let unclassified = Entry {
+            content: "this is not beancount".to_string(),
+            date: None,
+            entry_type: EntryType::OtherEntry,
+            secondary_key: None,
+            line_number: 1,
+        };
+        let historic = construct_dated_entry(
+            "1985-01-01 custom \"budget\" Expenses:Food \"monthly\" 100.00 EUR",
+            NaiveDate::from_ymd_opt(1985, 1, 1).unwrap(),
+        )
+        .unwrap();
+        for descending in [false, true] {
+            let options = SortOptions {
+                descending,
+                ..Default::default()
+            };
+            let sorted = sort_entries(vec![historic.clone(), unclassified.clone()], &options)
+                .unwrap()
+                .into_iter()
+                .filter(|e| e.entry_type != EntryType::Section)
+                .map(|e| e.date)
+                .collect::<Vec<Option<NaiveDate>>>();
+            if descending {
+                assert_eq!(sorted, [historic.date, None]);
+            } else {
+                assert_eq!(sorted, [None, historic.date]);
+            }
+        }
+        #[cfg(feature = "serde")]
+        assert_eq!(
+            serde_json::to_value(&unclassified).unwrap()["date"],
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn test_section_deco() {
+        let default_heading = SectionDeco::default().heading("Prices");
+        assert_eq!(
+            default_heading,
+            ";€€€€€€€€€€€€€€\n;€€€€Prices€€€€\n;€€€€€€€€€€€€€€"
+        );
+        let deco = SectionDeco {
+            deco: '=',
+            count: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            deco.heading("Prices"),
+            ";==========\n;==Prices==\n;=========="
+        );
+        assert!(deco.is_heading(";==Prices=="));
+        assert!(!deco.is_heading("; = Prices ="));
+        assert!(!deco.is_heading(";== TODO reconcile December"));
+
+        let content = format!(
+            "{}\n2021-01-01 price USD 0.90 EUR\n",
+            deco.heading("Prices")
+        );
+        let options = ParseOptions {
+            section_deco: deco.clone(),
+            ..Default::default()
+        };
+        let path = env::temp_dir().join("beancount-sort_test_section_deco.beancount");
+        std::fs::write(&path, &content).unwrap();
+        let entries = find_entries(read_file(&path).unwrap(), &options)
+            .unwrap()
+            .entries;
+        remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        let sort_options = SortOptions {
+            section_deco: deco.clone(),
+            ..Default::default()
+        };
+        let sorted = sort_entries(entries, &sort_options).unwrap();
+        assert!(sorted.iter().any(|e| e.content == deco.heading("Prices")));
+        assert!(!sorted.iter().any(|e| e.content.contains(DECO)));
+    }
+
+    #[test]
+    fn test_month_headers() {
+        let content = "2023-04-02 * \"Bakery\"\n\
+                       \x20 Expenses:Food 3.00 EUR\n\
+                       \x20 Assets:Cash\n\
+                       2023-03-31 * \"Zoo\"\n\
+                       2023-04-01 * \"Coffee\"\n\
+                       2023-03-01 price USD 0.90 EUR\n\
+                       2024-04-01 * \"Coffee\"\n";
+        let options = SortOptions {
+            month_headers: true,
+            ..Default::default()
+        };
+        let sort = |name: &str, content: &str| {
+            sort_entries(parse_str(name, content), &options)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.content + "\n")
+                .collect::<String>()
+        };
+        let sorted = sort("month_headers", content);
+        let transactions = &sorted[sorted.find("Transactions").unwrap()..];
+        let lines: Vec<&str> = transactions
+            .lines()
+            .skip(2)
+            .filter(|l| l.starts_with(';') || RE_DATE.is_match(l))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "; ---- 2023-03 ----",
+                "2023-03-31 * \"Zoo\"",
+                "; ---- 2023-04 ----",
+                "2023-04-01 * \"Coffee\"",
+                "2023-04-02 * \"Bakery\"",
+                "; ---- 2024-04 ----",
+                "2024-04-01 * \"Coffee\"",
+            ]
+        );
+        assert_eq!(sort("month_headers_resorted", &sorted), sorted);
+        let without_headers = sort_entries(
+            parse_str("month_headers_removed", &sorted),
+            &SortOptions::default(),
+        )
+        .unwrap();
+        assert!(!without_headers.iter().any(|e| e.content.contains("; ----")));
+    }
+
+    #[test]
+    fn test_validate_entries() {
+        let entries = parse_str(
+            "validate_entries",
+            "2023-01-01 * \"Bakery\"\n\
+             2023-01-20 price USD 0.90 EUR\n\
+             2023-03-01 * \"Bakery\"\n\
+             2023-03-02 * \"Zoo\"\n\
+             2023-02-01 * \"Coffee\"\n",
+        );
+        let date = |m, d| NaiveDate::from_ymd_opt(2023, m, d).unwrap();
+        let sorted = sort_entries(entries.clone(), &SortOptions::default()).unwrap();
+        assert_eq!(validate_entries(&sorted, 31), []);
+        assert_eq!(validate_entries(&sorted, 30), [(date(1, 1), date(2, 1))]);
+        assert_eq!(
+            validate_entries(&sorted, 27),
+            [(date(1, 1), date(2, 1)), (date(2, 1), date(3, 1))]
+        );
+        let options = SortOptions {
+            descending: true,
+            ..Default::default()
+        };
+        let sorted = sort_entries(entries, &options).unwrap();
+        assert_eq!(validate_entries(&sorted, 30), [(date(2, 1), date(1, 1))]);
+    }
+
+    #[test]
+    fn test_fallback_section() {
+        let content = "2023-01-02 price USD 0.93 EUR\n\
+                       2023-01-01 open Assets:Cash\n\
+                       2023-01-03 event \"location\" \"Berlin\"\n";
+        let (section_order, missing) = parse_section_order("Accounts,Transactions").unwrap();
+        let section_order: Vec<String> = section_order
+            .into_iter()
+            .filter(|s| !missing.contains(s))
+            .collect();
+        let options = SortOptions {
+            section_order,
+            fallback_section: Some("Transactions".to_string()),
+            ..Default::default()
+        };
+        let entries = parse_str("fallback_section", content);
+        let sorted_entries = sort_entries(entries.clone(), &options).unwrap();
+        let types: Vec<EntryType> = sorted_entries
+            .iter()
+            .filter(|e| e.entry_type != EntryType::Section)
+            .map(|e| e.entry_type.clone())
+            .collect();
+        assert_eq!(
+            types,
+            [EntryType::Account, EntryType::Price, EntryType::Event]
+        );
+        assert_eq!(
+            count_sections(&entries, &options).unwrap(),
+            [
+                ("Header".to_string(), 0),
+                ("Accounts".to_string(), 1),
+                ("Transactions".to_string(), 2)
+            ]
+        );
+
+        let options = SortOptions {
+            fallback_section: Some("Prices".to_string()),
+            ..options
+        };
+        assert!(sort_entries(entries, &options).is_err());
+    }
+
+    #[test]
+    fn test_keep_sections() {
+        let content = ";;; Accounts\n\
+                       2023-01-02 open Assets:Cash\n\
+                       2023-01-01 open Assets:Giro\n\
+                       ;€€€€ My Transactions €€€€\n\
+                       2023-01-05 * \"Bakery\"\n\
+                       2023-01-03 * \"Zoo\"\n";
+        let parse_options = ParseOptions {
+            keep_sections: true,
+            ..Default::default()
+        };
+        let entries = parse_str_with("keep_sections", content, &parse_options);
+        let options = SortOptions {
+            keep_sections: true,
+            ..Default::default()
+        };
+        let sorted: Vec<String> = sort_entries(entries, &options)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.content)
+            .collect();
+        assert_eq!(
+            sorted,
+            [
+                "2023-01-01 open Assets:Giro",
+                ";;; Accounts\n2023-01-02 open Assets:Cash",
+                ";€€€€ My Transactions €€€€",
+                "2023-01-03 * \"Zoo\"",
+                "2023-01-05 * \"Bakery\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comments_like_headings() {
+        // comments that start like a heading are kept, only complete headings are removed
+        let deco = SectionDeco {
+            deco: '-',
+            count: 2,
+            ..Default::default()
+        };
+        let options = ParseOptions {
+            section_deco: deco.clone(),
+            ..Default::default()
+        };
+        let content = format!(
+            ";-- TODO reconcile December\n\
+             2021-12-01 open Assets:Cash\n\
+             ;€€€€ checked with the bank\n\
+             2021-12-02 open Assets:Giro\n\
+             {}\n\
+             2021-12-03 * \"Bakery\"\n",
+            deco.heading("Transactions")
+        );
+        let entries = parse_str_with("comments_like_headings", &content, &options);
+        let contents: Vec<&str> = entries.iter().map(|e| e.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                ";-- TODO reconcile December\n2021-12-01 open Assets:Cash",
+                ";€€€€ checked with the bank\n2021-12-02 open Assets:Giro",
+                "2021-12-03 * \"Bakery\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comments_like_banner_names() {
+        // a single line with a section name isn't a heading without the rules around it
+        let content = "2021-01-01 open Assets:Cash\n\
+                       ;*Transactions*\n\
+                       2021-01-02 * \"Bakery\"\n\
+                       ;\"Notes\"\n\
+                       ;*****\n\
+                       ;~~Prices~~\n\
+                       2021-01-03 price USD 0.90 EUR\n";
+        let entries = parse_str("comments_like_banner_names", content);
+        let contents: Vec<&str> = entries.iter().map(|e| e.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                "2021-01-01 open Assets:Cash",
+                ";*Transactions*\n2021-01-02 * \"Bakery\"",
+                ";\"Notes\"\n;*****\n;~~Prices~~\n2021-01-03 price USD 0.90 EUR",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deco_width() {
+        let deco = SectionDeco {
+            deco: '-',
+            count: 2,
+            width: 12,
+        };
+        assert_eq!(
+            deco.heading("Prices"),
+            ";------------\n;--Prices----\n;------------"
+        );
+        assert_eq!(
+            deco.heading("Transactions"),
+            ";----------------\n;--Transactions--\n;----------------"
+        );
+        assert_eq!(banner_deco(";--Prices----"), Some('-'));
+        assert_eq!(banner_deco(";==Other Entries=="), Some('='));
+        assert_eq!(banner_deco(";--Groceries--"), None);
+        assert_eq!(banner_deco("; Prices"), None);
+        assert_eq!(banner_deco(";\"Notes\""), None);
+        assert_eq!(banner_deco(";'Prices'"), None);
+        assert!(is_banner_rule(";------------", '-'));
+        assert!(!is_banner_rule(";-----=", '-'));
+    }
+
+    #[test]
+    fn test_change_deco() {
+        let content = "; my prices\n\
+                       2021-01-02 price USD 0.90 EUR\n\
+                       2021-01-01 open Assets:Giro\n\
+                       2021-01-03 * \"Bakery\"\n";
+        let sort_with = |content: &str, deco: &SectionDeco| {
+            let parse_options = ParseOptions {
+                section_deco: deco.clone(),
+                ..Default::default()
+            };
+            let sort_options = SortOptions {
+                section_deco: deco.clone(),
+                ..Default::default()
+            };
+            let entries = parse_str_with("change_deco", content, &parse_options);
+            sort_entries(entries, &sort_options)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.content + "\n")
+                .collect::<String>()
+        };
+        let equals = SectionDeco {
+            deco: '=',
+            count: 3,
+            width: 0,
+        };
+        let dashes = SectionDeco {
+            deco: '-',
+            count: 2,
+            width: 30,
+        };
+        for (first, second) in [(&equals, &dashes), (&dashes, &equals)] {
+            let sorted_first = sort_with(content, first);
+            let sorted_second = sort_with(&sorted_first, second);
+            assert_eq!(sorted_second, sort_with(content, second));
+            assert_eq!(sort_with(&sorted_second, first), sorted_first);
+            assert!(!sorted_second.contains(&first.deco.to_string().repeat(3)));
+            assert!(sorted_second.contains("; my prices\n2021-01-02 price"));
+        }
+    }
+
+    #[test]
+    fn test_no_headings() {
+        let decorated = format!(
+            "{}\n2021-01-01 open Assets:Giro\n\n{}\n2021-01-02 * \"Bakery\"\n",
+            SectionDeco::default().heading("Accounts"),
+            SectionDeco::default().heading("Transactions")
+        );
+        let options = SortOptions {
+            no_headings: true,
+            ..Default::default()
+        };
+        let entries = sort_entries(parse_str("no_headings", &decorated), &options).unwrap();
+        assert!(entries.iter().all(|e| e.entry_type != EntryType::Section));
+        let ledger_file = LedgerFile {
+            path: PathBuf::from("no_headings"),
+            file: Input::empty(),
+            entries,
+            bom: false,
+            line_ending: LineEnding::Lf,
+            encoding: None,
+        };
+        assert_eq!(
+            ledger_file.format_ledger_file(BlankLines::Entries),
+            "2021-01-01 open Assets:Giro\n\n2021-01-02 * \"Bakery\"\n"
+        );
+    }
+
+    #[test]
+    fn test_blank_lines() {
+        let content = "2021-01-02 open Assets:Cash\n\
+                       2021-01-01 open Assets:Giro\n\
+                       2021-01-03 * \"Bakery\"\n\
+                       \x20 Assets:Cash -3 EUR\n";
+        let options = SortOptions {
+            section_order: vec![
+                "Header".to_string(),
+                "Accounts".to_string(),
+                "Prices".to_string(),
+                "Transactions".to_string(),
+            ],
+            section_deco: SectionDeco {
+                deco: '=',
+                count: 1,
+                width: 0,
+            },
+            // the empty Prices section shows the blank lines between two headings
+            always_show_sections: true,
+            ..Default::default()
+        };
+        let ledger_file = LedgerFile {
+            path: PathBuf::from("blank_lines"),
+            file: Input::empty(),
+            entries: sort_entries(parse_str("blank_lines", content), &options).unwrap(),
+            bom: false,
+            line_ending: LineEnding::Lf,
+            encoding: None,
+        };
+        assert_eq!(
+            ledger_file.format_ledger_file(BlankLines::None),
+            ";==========\n;=Accounts=\n;==========\n\
+             2021-01-01 open Assets:Giro\n\
+             2021-01-02 open Assets:Cash\n\
+             ;========\n;=Prices=\n;========\n\
+             ;==============\n;=Transactions=\n;==============\n\
+             2021-01-03 * \"Bakery\"\n\
+             \x20 Assets:Cash -3 EUR\n"
+        );
+        assert_eq!(
+            ledger_file.format_ledger_file(BlankLines::Sections),
+            ";==========\n;=Accounts=\n;==========\n\
+             \n\
+             2021-01-01 open Assets:Giro\n\
+             2021-01-02 open Assets:Cash\n\
+             \n\
+             ;========\n;=Prices=\n;========\n\
+             \n\
+             ;==============\n;=Transactions=\n;==============\n\
+             \n\
+             2021-01-03 * \"Bakery\"\n\
+             \x20 Assets:Cash -3 EUR\n"
+        );
+        assert_eq!(
+            ledger_file.format_ledger_file(BlankLines::Entries),
+            ";==========\n;=Accounts=\n;==========\n\
+             \n\
+             2021-01-01 open Assets:Giro\n\
+             \n\
+             2021-01-02 open Assets:Cash\n\
+             \n\
+             ;========\n;=Prices=\n;========\n\
+             \n\
+             ;==============\n;=Transactions=\n;==============\n\
+             \n\
+             2021-01-03 * \"Bakery\"\n\
+             \x20 Assets:Cash -3 EUR\n"
+        );
+        assert_eq!(
+            "sections".parse::<BlankLines>().unwrap(),
+            BlankLines::Sections
+        );
+        assert!("some".parse::<BlankLines>().is_err());
+    }
+
+    #[test]
+    fn test_end_of_file() {
+        // the Transactions section is empty and written last
+        let entries = sort_entries(
+            parse_str("end_of_file", "2021-01-01 open Assets:Giro\n\n\n"),
+            &SortOptions::default(),
+        )
+        .unwrap();
+        let ledger_file = LedgerFile {
+            path: PathBuf::from("end_of_file"),
+            file: Input::empty(),
+            entries,
+            bom: false,
+            line_ending: LineEnding::Crlf,
+            encoding: None,
+        };
+        for blank_lines in [BlankLines::None, BlankLines::Sections, BlankLines::Entries] {
+            let output = ledger_file.format_ledger_file(blank_lines);
+            let bytes = output.as_bytes();
+            assert_eq!(bytes[bytes.len() - 2..], *b"\r\n", "{:?}", blank_lines);
+            assert_ne!(bytes[bytes.len() - 3], b'\n', "{:?}", blank_lines);
+            assert!(!output.contains("\r\n\r\n\r\n"), "{:?}", blank_lines);
+        }
+        let ledger_file = LedgerFile {
+            entries: Vec::new(),
+            ..ledger_file
+        };
+        assert_eq!(ledger_file.format_ledger_file(BlankLines::Entries), "");
+    }
+
+    #[test]
+    fn test_sort_entries_stable() {
+        // the same date, type and payee, only the narration differs
+        let content = "2021-01-01 * \"Bakery\" \"Zwieback\"\n\
+                       2021-01-01 * \"Bakery\" \"Apple pie\"\n\
+                       2021-01-01 * \"Bakery\" \"Muffin\"\n\
+                       2020-12-31 * \"Cinema\"\n";
+        let entries = parse_str("sort_entries_stable", content);
+        let sorted: Vec<String> = sort_entries_stable(entries.clone(), &SortOptions::default())
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.entry_type == EntryType::Transaction)
+            .map(|e| e.content)
+            .collect();
+        assert_eq!(
+            sorted,
+            [
+                "2020-12-31 * \"Cinema\"",
+                "2021-01-01 * \"Bakery\" \"Zwieback\"",
+                "2021-01-01 * \"Bakery\" \"Apple pie\"",
+                "2021-01-01 * \"Bakery\" \"Muffin\"",
+            ]
+        );
+        let mut reversed = entries;
+        reversed.reverse();
+        let sorted: Vec<String> = sort_entries_stable(reversed, &SortOptions::default())
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.entry_type == EntryType::Transaction)
+            .map(|e| e.content)
+            .collect();
+        assert_eq!(
+            sorted[1..],
+            [
+                "2021-01-01 * \"Bakery\" \"Muffin\"",
+                "2021-01-01 * \"Bakery\" \"Apple pie\"",
+                "2021-01-01 * \"Bakery\" \"Zwieback\""
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skip_empty_sections() {
+        let content = "2021-01-02 * \"Bakery\"\n2021-01-01 * \"Zoo\"\n";
+        let headings = |options: &SortOptions| {
+            sort_entries(parse_str("skip_empty_sections", content), options)
+                .unwrap()
+                .into_iter()
+                .filter(|e| e.entry_type == EntryType::Section)
+                .map(|e| e.content)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            headings(&SortOptions::default()),
+            [SectionDeco::default().heading("Transactions")]
+        );
+        let options = SortOptions {
+            always_show_sections: true,
+            ..Default::default()
+        };
+        assert_eq!(headings(&options).len(), SECTIONS.len() - 4);
+    }
+}
@@ -1,519 +1,1192 @@
 use anyhow::{anyhow, Context, Result};
-use chrono::naive::NaiveDate;
-use log::info;
-use regex::Regex;
+use beancount_sort::{
+    backup_file, count_sections, date_range, decode, find_duplicates, find_entries,
+    find_future_entries, format_diff, is_deco_char, is_same_file, is_written, open_output,
+    parse_encoding, parse_section_order, read_file, remove_duplicate_options, sort_entries,
+    split_by_date_range, unified_diff, validate_entries, write_atomically, AccountsOrder,
+    BlankLines, CommoditiesOrder, DiffFormat, Entry, Input, LedgerFile, OrphanComments,
+    ParseOptions, PricesOrder, SectionDeco, SortOptions, TieBreak, DECO, NDECO, SECTIONS,
+    STDIN_NAME, STDIN_PATH, STDOUT_PATH,
+};
+use chrono::{Local, NaiveDate};
+use clap::{
+    builder::{PossibleValuesParser, TypedValueParser},
+    parser::ValueSource,
+    ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser,
+};
+use clap_complete::Shell;
+use log::{info, LevelFilter};
+use serde::Deserialize;
 use std::{
-    env,
-    ffi::OsStr,
-    fs::{remove_file, OpenOptions},
-    io::{prelude::*, BufRead, BufReader},
-    mem,
+    env, fs,
+    io::{self, Write},
     path::{Path, PathBuf},
+    process,
 };
-use structopt::StructOpt;
 
-#[derive(StructOpt)]
-#[structopt(name = "beancount-sort", about = "Sorts a beancount file.")]
+#[derive(Parser, Clone)]
+#[command(name = "beancount-sort", about = "Sorts a beancount file.")]
 struct Cli {
-    // the path to the beancount file we want to sort
-    #[structopt(
+    // the paths to the beancount files we want to sort
+    #[arg(
         short,
         long,
-        parse(from_os_str),
-        help = "Filepath which has to be sorted."
+        required_unless_present_any = ["completions", "example_config", "manifest"],
+        num_args = 1,
+        help = "Filepath which has to be sorted. Can be given multiple times to merge several files into one. Use - to read from stdin."
     )]
-    file: PathBuf,
-    // Comma-separated list of section names
-    // sections: String,
-    #[structopt(
+    file: Vec<PathBuf>,
+    #[arg(
         short,
         long,
-        parse(from_os_str),
-        help = "Where to write the sorted file?"
+        help = "Where to write the sorted file? Use - to write to stdout."
     )]
-    out: PathBuf,
-    #[structopt(
+    out: Option<PathBuf>,
+    #[arg(
+        short,
+        long,
+        help = "Overwrite the input file with the sorted result (a backup is created first)."
+    )]
+    in_place: bool,
+    #[arg(
         short,
         long,
         default_value = "0",
         help = "Leave the first n lines where they are. (e.g. for modline)"
     )]
     skipn: usize,
-    #[structopt(long, help = "Leave one empty line between each entry?")]
+    #[arg(
+        long,
+        default_value = "none",
+        value_parser = PossibleValuesParser::new(["none", "sections", "entries"])
+            .map(|s| s.parse::<BlankLines>().unwrap()),
+        help = "Where to leave empty lines: nowhere, around the section headings or after every entry."
+    )]
+    blank_lines: BlankLines,
+    #[arg(
+        long,
+        conflicts_with = "blank_lines",
+        help = "Deprecated, use --blank-lines entries instead."
+    )]
     spaces: bool,
+    #[arg(
+        long,
+        help = "Print a diff of the changes instead of writing the sorted file. No backup is created."
+    )]
+    dry_run: bool,
+    #[arg(
+        long,
+        value_parser = PossibleValuesParser::new(["unified", "patch"])
+            .map(|s| s.parse::<DiffFormat>().unwrap()),
+        help = "Format of the diff printed by --dry-run (implies --dry-run). \"patch\" can be applied with patch -p1 or git apply."
+    )]
+    diff_format: Option<DiffFormat>,
+    #[arg(
+        long,
+        help = "Only check if the file is already sorted. Exits with code 1 if it is not. No file is written."
+    )]
+    check: bool,
+    #[arg(
+        long,
+        help = "Fail if a pushmeta block can't be kept together without changing the sort order."
+    )]
+    strict_meta_blocks: bool,
+    // Comma-separated list of section names
+    #[arg(
+        long,
+        help = "Comma-separated order of the sections, e.g. \"Options,Prices,Accounts\". Missing sections are appended."
+    )]
+    section_order: Option<String>,
+    #[arg(
+        long,
+        value_parser = PossibleValuesParser::new(SECTIONS),
+        help = "Sort the entries of the sections missing in --section-order into this section instead of appending the missing sections."
+    )]
+    fallback_section: Option<String>,
+    #[arg(
+        long,
+        num_args = 1,
+        value_parser = PossibleValuesParser::new(SECTIONS),
+        help = "Only write this section (and the skipped lines). Can be given multiple times."
+    )]
+    filter_section: Vec<String>,
+    #[arg(
+        long,
+        help = "Write custom directives (e.g. fava budgets) into their own section instead of \"Other Entries\"."
+    )]
+    custom_section: bool,
+    #[arg(
+        long,
+        help = "Write notes into their own section instead of \"Other Entries\"."
+    )]
+    notes_section: bool,
+    #[arg(
+        long,
+        help = "Write queries into their own section instead of \"Other Entries\"."
+    )]
+    queries_section: bool,
+    #[arg(
+        long,
+        help = "Replace include directives by the content of the included files."
+    )]
+    follow_includes: bool,
+    #[arg(
+        long,
+        help = "Write plugins into the Options section instead of their own Plugins section."
+    )]
+    plugins_with_options: bool,
+    #[arg(long, help = "Don't create a backup of the input files.")]
+    no_backup: bool,
+    #[arg(
+        long,
+        conflicts_with = "no_backup",
+        help = "Write the backups with a timestamp into this directory instead of next to the input files."
+    )]
+    backup_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        default_value = "keep",
+        value_parser = PossibleValuesParser::new(["keep", "attach-next", "attach-prev"])
+            .map(|s| s.parse::<OrphanComments>().unwrap()),
+        help = "What to do with a comment that is separated from the next entry by an empty line."
+    )]
+    orphan_comments: OrphanComments,
+    #[arg(
+        long,
+        help = "Attach a comment directly below an entry to that entry instead of the next one."
+    )]
+    comments_follow: bool,
+    #[arg(
+        long,
+        help = "Keep comments that aren't directly above a dated entry behind the entry in front of them instead of sorting them."
+    )]
+    keep_leading_comments: bool,
+    #[arg(
+        long,
+        default_value = "date",
+        value_parser = PossibleValuesParser::new(["date", "payee", "narration", "amount", "stable"])
+            .map(|s| s.parse::<TieBreak>().unwrap()),
+        help = "How entries with the same date are ordered. \"date\" orders them by payee, account or currency, \"stable\" keeps their order."
+    )]
+    tiebreak: TieBreak,
+    #[arg(
+        long,
+        conflicts_with = "tiebreak",
+        value_parser = PossibleValuesParser::new(["input", "payee", "amount"])
+            .map(|s| s.parse::<TieBreak>().unwrap()),
+        help = "Shorthand for --tiebreak: keep same-date entries in input order or order them by payee or amount."
+    )]
+    same_date_order: Option<TieBreak>,
+    #[arg(
+        long,
+        default_value = "date",
+        value_parser = PossibleValuesParser::new(["date", "name"])
+            .map(|s| s.parse::<AccountsOrder>().unwrap()),
+        help = "Order the Accounts section by date or by account name."
+    )]
+    accounts_order: AccountsOrder,
+    #[arg(
+        long,
+        default_value = "date",
+        value_parser = PossibleValuesParser::new(["date", "symbol"])
+            .map(|s| s.parse::<CommoditiesOrder>().unwrap()),
+        help = "Order the Commodities section by date or by commodity symbol."
+    )]
+    commodities_order: CommoditiesOrder,
+    #[arg(
+        long,
+        default_value = "date",
+        value_parser = PossibleValuesParser::new(["date", "commodity"])
+            .map(|s| s.parse::<PricesOrder>().unwrap()),
+        help = "Order the Prices section by date or group it by commodity."
+    )]
+    prices_order: PricesOrder,
+    #[arg(long, help = "Only sort entries on or after this date (YYYY-MM-DD).")]
+    date_from: Option<NaiveDate>,
+    #[arg(long, help = "Only sort entries on or before this date (YYYY-MM-DD).")]
+    date_to: Option<NaiveDate>,
+    #[arg(
+        long,
+        help = "Append entries outside of --date-from and --date-to unsorted at the end instead of dropping them."
+    )]
+    keep_out_of_range: bool,
+    #[arg(
+        long,
+        help = "Sort the entries of each section from the newest to the oldest."
+    )]
+    sort_desc: bool,
+    #[arg(
+        long,
+        help = "Sort only the Transactions and Prices sections from the newest to the oldest."
+    )]
+    reverse: bool,
+    #[arg(
+        long,
+        help = "Write balance assertions into the Transactions section in front of the transactions of the same date."
+    )]
+    interleave_balances: bool,
+    #[arg(
+        long,
+        help = "Insert a comment like \"; ---- 2023-04 ----\" in front of the first transaction of every month."
+    )]
+    month_headers: bool,
+    #[arg(
+        long,
+        help = "Keep entries with an invalid date together with their indented lines in \"Other Entries\" instead of stopping with an error."
+    )]
+    lenient_dates: bool,
+    #[arg(
+        long,
+        help = "Keep org-mode headings (lines starting with *) like comments instead of removing them."
+    )]
+    keep_org_headings: bool,
+    #[arg(
+        long,
+        help = "Keep the section headings of the file and only sort the entries between them instead of generating the sections."
+    )]
+    keep_sections: bool,
+    #[arg(
+        long,
+        conflicts_with = "keep_sections",
+        help = "Group the entries by section without writing the section headings."
+    )]
+    no_headings: bool,
+    #[arg(
+        long,
+        help = "Write the headings of empty sections as well. By default they are left out."
+    )]
+    always_show_sections: bool,
+    #[arg(
+        long,
+        default_value = "utf-8",
+        help = "Encoding of the files, e.g. latin1 or windows-1252. The sorted file is written with the same encoding."
+    )]
+    encoding: String,
+    #[arg(
+        long,
+        help = "Keep lines that can't be classified in \"Other Entries\" instead of stopping with an error."
+    )]
+    lenient: bool,
+    #[arg(
+        long,
+        help = "Keep the UTF-8 byte order mark at the start of the file. By default it is removed."
+    )]
+    keep_bom: bool,
+    #[arg(
+        long,
+        default_value = "auto",
+        value_parser = ["auto", "lf", "crlf"],
+        help = "Line ending of the sorted file. \"auto\" uses the line ending of most lines of the input."
+    )]
+    line_ending: String,
+    #[arg(
+        long,
+        help = "Warn about entries on the same date that only differ in whitespace."
+    )]
+    warn_duplicates: bool,
+    #[arg(
+        long,
+        help = "Warn about entries dated after today, e.g. because of a mistyped year."
+    )]
+    warn_future: bool,
+    #[arg(
+        long,
+        value_name = "DAYS",
+        help = "Warn if consecutive transactions are more than this many days apart."
+    )]
+    max_date_gap: Option<u32>,
+    #[arg(
+        long,
+        visible_alias = "deco-char",
+        default_value = DECO,
+        help = "Character that decorates the section headings."
+    )]
+    section_deco: char,
+    #[arg(
+        long,
+        default_value_t = NDECO,
+        help = "Number of decoration characters in front of and behind the section names."
+    )]
+    section_deco_count: usize,
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Fill the section headings up to this width so that they line up. 0 fits every heading to its name."
+    )]
+    deco_width: usize,
+    #[arg(
+        long,
+        help = "Print the number of entries of each section and the date range to stderr."
+    )]
+    stats: bool,
+    #[arg(
+        long,
+        default_value = "beancount",
+        value_parser = ["beancount", "json"],
+        help = "Write the sorted entries as a beancount file or as a JSON array of entries."
+    )]
+    output_format: String,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Write the transactions and prices of every year to DIR/<year>.beancount and include them in the sorted file."
+    )]
+    split_by_year: Option<PathBuf>,
+    #[arg(
+        long,
+        conflicts_with = "split_by_year",
+        help = "Parse the sorted file again before writing it and fail with a diff if sorting it once more changes it."
+    )]
+    assert_sorted: bool,
+    #[arg(
+        short,
+        long,
+        action = ArgAction::Count,
+        help = "Print what is done. Give it twice (-vv) for debug output."
+    )]
+    verbose: u8,
+    #[arg(
+        long,
+        value_enum,
+        help = "Print the completion script for this shell and exit."
+    )]
+    completions: Option<Shell>,
+    #[arg(
+        long,
+        help = "Read default options from this TOML file instead of $XDG_CONFIG_HOME/beancount-sort/config.toml."
+    )]
+    config: Option<PathBuf>,
+    #[arg(long, help = "Print an example configuration file and exit.")]
+    example_config: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["file", "out"],
+        help = "Sort every file listed in this TOML file, see the README. The files are sorted in place unless they have an out path."
+    )]
+    manifest: Option<PathBuf>,
 }
 
-const SECTIONS: [&str; 7] = [
-    "Header",
-    "Options",
-    "Accounts",
-    "Commodities",
-    "Other Entries",
-    "Prices",
-    "Transactions",
-];
-const NDECO: usize = 4; // number of DECO to use at section headings
-const DECO: &str = "€";
+/// Example configuration that documents all keys of [Config]
+const EXAMPLE_CONFIG: &str = include_str!("../config.example.toml");
 
-/// The main Object that holds all information about a ledger file.
-/// Is returned by the function [read_file]
-#[derive(Debug)]
-struct LedgerFile {
-    file: std::fs::File,
-    entries: Vec<Entry>,
+/// Default options read from a TOML file. Every key corresponds to the command line flag
+/// of the same name, flags given on the command line take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    skipn: Option<usize>,
+    spaces: Option<bool>,
+    blank_lines: Option<BlankLines>,
+    section_order: Option<String>,
+    fallback_section: Option<String>,
+    custom_section: Option<bool>,
+    notes_section: Option<bool>,
+    queries_section: Option<bool>,
+    plugins_with_options: Option<bool>,
+    follow_includes: Option<bool>,
+    strict_meta_blocks: Option<bool>,
+    no_backup: Option<bool>,
+    backup_dir: Option<PathBuf>,
+    orphan_comments: Option<OrphanComments>,
+    comments_follow: Option<bool>,
+    keep_leading_comments: Option<bool>,
+    tiebreak: Option<TieBreak>,
+    accounts_order: Option<AccountsOrder>,
+    commodities_order: Option<CommoditiesOrder>,
+    prices_order: Option<PricesOrder>,
+    sort_desc: Option<bool>,
+    reverse: Option<bool>,
+    interleave_balances: Option<bool>,
+    month_headers: Option<bool>,
+    lenient_dates: Option<bool>,
+    keep_org_headings: Option<bool>,
+    keep_sections: Option<bool>,
+    no_headings: Option<bool>,
+    always_show_sections: Option<bool>,
+    encoding: Option<String>,
+    lenient: Option<bool>,
+    keep_bom: Option<bool>,
+    line_ending: Option<String>,
+    warn_duplicates: Option<bool>,
+    warn_future: Option<bool>,
+    max_date_gap: Option<u32>,
+    split_by_year: Option<PathBuf>,
+    assert_sorted: Option<bool>,
+    stats: Option<bool>,
+    section_deco: Option<char>,
+    section_deco_count: Option<usize>,
+    deco_width: Option<usize>,
 }
-impl LedgerFile {
-    fn write_ledger_file(self, path: &Path, spaces: &bool) -> Result<()> {
-        // check if path exist
-        // match for every entry type and append content to file
-        if path.exists() {
-            remove_file(path)?;
+
+impl Config {
+    /// Returns `$XDG_CONFIG_HOME/beancount-sort/config.toml`,
+    /// with `~/.config` if `XDG_CONFIG_HOME` isn't set.
+    fn default_path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("beancount-sort").join("config.toml"))
+    }
+
+    /// Reads the config file given with `--config`. Without `--config` the default path is used
+    /// if the file exists there.
+    fn load(path: Option<&Path>) -> Result<Config> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => match Config::default_path() {
+                Some(path) if path.is_file() => path,
+                _ => return Ok(Config::default()),
+            },
         };
-        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
-        for entry in self.entries {
-            let output = entry.content;
-            if let Err(e) = writeln!(file, "{}", &output) {
-                return Err(anyhow!("Couldnt write to file: {}", e));
-            };
-            if *spaces {
-                // insert empty line if "spaces" flag is given
-                writeln!(file)?
+        info!("Reading config file {:?}", path);
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("unable to read config file '{}'", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("invalid config file '{}'", path.display()))
+    }
+
+    /// Sets all options of `args` that are given in the config file
+    /// but weren't given on the command line.
+    fn apply(self, args: &mut Cli, matches: &ArgMatches) {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        macro_rules! apply {
+            ($($field:ident),*) => {
+                $(
+                    if let Some(value) = self.$field {
+                        if !from_cli(stringify!($field)) {
+                            args.$field = value;
+                        }
+                    }
+                )*
             };
         }
-        Ok(())
+        apply!(
+            skipn,
+            spaces,
+            blank_lines,
+            custom_section,
+            notes_section,
+            queries_section,
+            plugins_with_options,
+            follow_includes,
+            strict_meta_blocks,
+            no_backup,
+            orphan_comments,
+            comments_follow,
+            keep_leading_comments,
+            tiebreak,
+            accounts_order,
+            commodities_order,
+            prices_order,
+            sort_desc,
+            reverse,
+            interleave_balances,
+            month_headers,
+            lenient_dates,
+            keep_org_headings,
+            keep_sections,
+            no_headings,
+            always_show_sections,
+            encoding,
+            lenient,
+            keep_bom,
+            line_ending,
+            warn_duplicates,
+            warn_future,
+            assert_sorted,
+            stats,
+            section_deco,
+            section_deco_count,
+            deco_width
+        );
+        if !from_cli("section_order") {
+            args.section_order = self.section_order.or(args.section_order.take());
+        }
+        if !from_cli("fallback_section") {
+            args.fallback_section = self.fallback_section.or(args.fallback_section.take());
+        }
+        if !from_cli("max_date_gap") {
+            args.max_date_gap = self.max_date_gap.or(args.max_date_gap.take());
+        }
+        if !from_cli("split_by_year") {
+            args.split_by_year = self.split_by_year.or(args.split_by_year.take());
+        }
+        if !from_cli("backup_dir") {
+            args.backup_dir = self.backup_dir.or(args.backup_dir.take());
+        }
     }
 }
 
-/// The Entry type holds one entry in a beancount file.
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct Entry {
-    content: String,
-    //#[derivative(Default(value = "NaiveDate::from_ymd(2021, 1, 1)"))]
-    date: NaiveDate,
-    entry_type: EntryType,
+/// A list of files that are sorted one after another, read from the TOML file given by `--manifest`.
+/// Relative paths are relative to the directory of the manifest.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    file: Vec<ManifestFile>,
 }
 
-/// All possible types of entries in a beancount file. Used by [Entry]
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum EntryType {
-    Account,
-    Option,
-    Commodity,
-    OtherEntry,
-    Price,
-    Transaction,
-    Indented,
-    Section,
-    Header,
-    Comment,
+/// A file of a [Manifest] with the options that differ from the command line.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ManifestFile {
+    path: PathBuf,
+    out: Option<PathBuf>,
+    skipn: Option<usize>,
+    section_order: Option<String>,
 }
 
-/// The type of a line. Returned by [get_line_type]
-#[derive(Debug, Clone)]
-enum Line {
-    Date(NaiveDate),
-    Option,
-    Comment,
-    Indent,
-    Empty,
-    Section,
+impl Manifest {
+    fn load(path: &Path) -> Result<Manifest> {
+        info!("Reading manifest {:?}", path);
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("unable to read manifest '{}'", path.display()))?;
+        let mut manifest: Manifest = toml::from_str(&content)
+            .with_context(|| format!("invalid manifest '{}'", path.display()))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for file in &mut manifest.file {
+            file.path = dir.join(&file.path);
+            file.out = file.out.as_ref().map(|out| dir.join(out));
+        }
+        Ok(manifest)
+    }
+
+    /// Returns the arguments to sort `file` with, based on the command line arguments.
+    fn file_args(file: &ManifestFile, args: &Cli) -> Cli {
+        let mut file_args = args.clone();
+        file_args.file = vec![file.path.clone()];
+        file_args.out = file.out.clone();
+        file_args.in_place = file.out.is_none();
+        file_args.skipn = file.skipn.unwrap_or(args.skipn);
+        if file.section_order.is_some() {
+            file_args.section_order = file.section_order.clone();
+        }
+        file_args
+    }
 }
 
-/// Reads a file at a given Path. Returns a Result with either a [LedgerFile] or an Error
-fn read_file(path: &Path) -> Result<LedgerFile> {
-    let display = path.display();
-    let ledger_file = LedgerFile {
-        file: std::fs::File::open(path).context(format!("unable to open '{}'", display))?,
-        entries: Vec::new(),
-    };
+impl Cli {
+    /// Where to leave empty lines, `--spaces` is the same as `--blank-lines entries`
+    fn blank_lines(&self) -> BlankLines {
+        if self.spaces {
+            BlankLines::Entries
+        } else {
+            self.blank_lines
+        }
+    }
+
+    /// True if one of the files is read from stdin
+    fn reads_stdin(&self) -> bool {
+        self.file.iter().any(|f| f == Path::new(STDIN_PATH))
+    }
+
+    /// Determines where the sorted file has to be written to.
+    /// Either `--out` or `--in-place` has to be given, but not both.
+    fn out_path(&self) -> Result<&Path> {
+        match (&self.out, self.in_place) {
+            (Some(_), true) => Err(anyhow!(
+                "--out and --in-place are mutually exclusive. Use --in-place to overwrite '{}' or --out to write somewhere else.",
+                self.file[0].display()
+            )),
+            (Some(out), false) => Ok(out),
+            (None, true) if self.output_format == "json" => Err(anyhow!(
+                "--in-place can't be used with --output-format json because it would replace the beancount file. Use --out instead."
+            )),
+            (None, true) if self.reads_stdin() => Err(anyhow!(
+                "--in-place can't be used when reading from stdin. Use --out instead."
+            )),
+            (None, true) if self.file.len() > 1 => Err(anyhow!(
+                "--in-place can't be used when merging multiple files. Use --out instead."
+            )),
+            (None, true) => Ok(&self.file[0]),
+            (None, false) => Err(anyhow!(
+                "No output file given. Use --out <path> or --in-place."
+            )),
+        }
+    }
+}
+
+/// Reads and parses all given files and merges their entries into one [LedgerFile].
+/// Options that appear in more than one file are only kept once.
+/// With `warn_duplicates` a warning is printed for every entry that seems to be pasted twice.
+fn read_ledger_files(
+    files: &[PathBuf],
+    options: &ParseOptions,
+    warn_duplicates: bool,
+) -> Result<LedgerFile> {
+    let mut ledger_file = find_entries(read_file(&files[0])?, options)?;
+    for file in &files[1..] {
+        let mut other_file = find_entries(read_file(file)?, options)?;
+        ledger_file.entries.append(&mut other_file.entries);
+    }
+    for duplicate in remove_duplicate_options(&mut ledger_file.entries) {
+        eprintln!("Warning: Ignoring duplicate option {}", duplicate);
+    }
+    if warn_duplicates {
+        for (first, duplicate) in find_duplicates(&ledger_file.entries) {
+            eprintln!(
+                "Warning: Lines {}-{} duplicate lines {}-{}:\n{}",
+                duplicate.line_number,
+                duplicate.last_line_number(),
+                first.line_number,
+                first.last_line_number(),
+                duplicate
+            );
+        }
+    }
     Ok(ledger_file)
 }
 
-/// Creates a backup of the original beancount file.
-/// The new name is old_name_backup.old_extension
-fn backup_file(path: &Path) -> Result<()> {
-    let path_backup = path.with_file_name(format!(
-        "{}_backup.{}",
-        path.file_stem()
-            .unwrap_or(OsStr::new("finances"))
-            .to_string_lossy(),
-        path.extension()
-            .unwrap_or(OsStr::new("beancount"))
-            .to_string_lossy()
-    ));
-    std::fs::copy(&path, &path_backup).context(format!("unable to backup '{}'", path.display()))?;
-    println!(
-        "Backup done: {} -> {}",
-        path.display(),
-        path_backup.display()
-    );
-    Ok(())
+/// Reads, parses and sorts the input files as requested by the command line arguments.
+/// Entries outside of `--date-from` and `--date-to` are dropped with a warning
+/// or appended unsorted with `--keep-out-of-range`.
+fn sort_ledger_files(
+    args: &Cli,
+    parse_options: &ParseOptions,
+    sort_options: &SortOptions,
+) -> Result<LedgerFile> {
+    let mut ledger_file = read_ledger_files(&args.file, parse_options, args.warn_duplicates)?;
+    ledger_file.bom &= args.keep_bom;
+    if args.warn_future {
+        let today = Local::now().date_naive();
+        for entry in find_future_entries(&ledger_file.entries, today) {
+            eprintln!(
+                "Warning: Line {} is dated in the future ({}):\n{}",
+                entry.line_number,
+                entry.date.unwrap(), // unwrap is okay because only dated entries are returned
+                entry
+                    .content
+                    .lines()
+                    .find(|l| !l.starts_with(';'))
+                    .unwrap_or("")
+            );
+        }
+    }
+    if args.line_ending != "auto" {
+        ledger_file.line_ending = args.line_ending.parse()?;
+    }
+    if let (Some(from), Some(to)) = (args.date_from, args.date_to) {
+        if from > to {
+            return Err(anyhow!("--date-from {} is after --date-to {}", from, to));
+        }
+    }
+    let (entries, mut out_of_range) =
+        split_by_date_range(ledger_file.entries, args.date_from, args.date_to);
+    ledger_file.entries = sort_entries(entries, sort_options)?;
+    if !out_of_range.is_empty() {
+        if args.keep_out_of_range {
+            ledger_file.entries.append(&mut out_of_range);
+        } else {
+            eprintln!(
+                "Warning: Dropping {} entries outside of the date range. Use --keep-out-of-range to keep them.",
+                out_of_range.len()
+            );
+        }
+    }
+    if let Some(max_gap_days) = args.max_date_gap {
+        for (first, second) in validate_entries(&ledger_file.entries, max_gap_days) {
+            eprintln!(
+                "Warning: No transactions between {} and {} ({} days)",
+                first,
+                second,
+                (second - first).num_days().abs()
+            );
+        }
+    }
+    if args.stats {
+        print_stats(&ledger_file.entries, sort_options)?;
+    }
+    Ok(ledger_file)
 }
 
-/// Identifies the [Line] type of a given [str].
-fn get_line_type(line: &str, n: &usize) -> Result<Line> {
-    let re_date = Regex::new(r"^(\d{4}-[01]\d-[0-3]\d)")?;
-    let re_option = Regex::new(r"^(option)")?;
-    let re_comment = Regex::new(r"^(;+)")?;
-    let re_indented = Regex::new(r"(?m)(^ +)\S")?;
-    let re_empty = Regex::new(r"^.{0}$")?;
-    let re_section = Regex::new(format!("^;{}", DECO.repeat(NDECO)).as_str())?;
-    if re_date.is_match(line) {
-        let matches = re_date.captures(line);
-        let date_match = match matches {
-            Some(m) => m.get(1),
-            None => unreachable!(),
-        };
-        let date = match date_match {
-            Some(d) => d.as_str(),
-            None => unreachable!(),
-        };
-        Ok(Line::Date(NaiveDate::parse_from_str(date, "%Y-%m-%d")?))
-    } else if re_option.is_match(line) {
-        Ok(Line::Option)
-    // section has to be tested before comment
-    } else if re_section.is_match(line) {
-        Ok(Line::Section)
-    } else if re_comment.is_match(line) {
-        Ok(Line::Comment)
-    } else if re_indented.is_match(line) {
-        Ok(Line::Indent)
-    } else if re_empty.is_match(line) {
-        Ok(Line::Empty)
-    } else {
-        Err(anyhow!("Can't define line {}: \"{}\"", n, line))
+/// Prints the number of entries of each section, the total and the date range to stderr.
+fn print_stats(entries: &[Entry], sort_options: &SortOptions) -> Result<()> {
+    let counts = count_sections(entries, sort_options)?;
+    let width = counts.iter().map(|(s, _)| s.len()).max().unwrap_or(0) + 1;
+    for (section, count) in &counts {
+        eprintln!(
+            "{:<width$} {}",
+            format!("{}:", section),
+            count,
+            width = width
+        );
+    }
+    let total: usize = counts.iter().map(|(_, count)| count).sum();
+    eprintln!("{:<width$} {}", "Total:", total, width = width);
+    if let Some((first, last)) = date_range(entries) {
+        eprintln!("Dates from {} to {}", first, last);
     }
+    Ok(())
 }
 
-/// Creates an [Entry] from a given string and a date.
-fn construct_dated_entry(line: &str, date: NaiveDate) -> Result<Entry> {
-    let re = Regex::new(r"^\d{4}-[01]\d-[0-3]\d (\w+|\*|!)")?;
-    let matches = re.captures(line);
-    let directive_string = match matches {
-        Some(m) => m.get(1).unwrap().as_str(), // unwrap is okay because this can only be a match
-        None => return Err(anyhow!("Couldn't finde entry type.")),
-    };
-    let entry = match directive_string {
-        "*" | "!" => Entry {
-            content: line.to_owned(),
-            date,
-            entry_type: EntryType::Transaction,
-        },
-        "commodity" => Entry {
-            content: line.to_owned(),
-            date,
-            entry_type: EntryType::Commodity,
-        },
-        "price" => Entry {
-            content: line.to_owned(),
-            date,
-            entry_type: EntryType::Price,
-        },
-        "open" => Entry {
-            content: line.to_owned(),
-            date,
-            entry_type: EntryType::Account,
-        },
-        _ => Entry {
-            content: line.to_owned(),
-            date,
-            entry_type: EntryType::OtherEntry,
-        },
+fn main() -> Result<()> {
+    let matches = Cli::command().get_matches();
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // RUST_LOG overrides the level given by --verbose
+    let level = match args.verbose {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        _ => LevelFilter::Debug,
     };
-    Ok(entry)
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .init();
+    if args.example_config {
+        print!("{}", EXAMPLE_CONFIG);
+        return Ok(());
+    }
+    Config::load(args.config.as_deref())?.apply(&mut args, &matches);
+    args.dry_run |= args.diff_format.is_some();
+    if let Some(shell) = args.completions {
+        clap_complete::generate(
+            shell,
+            &mut Cli::command(),
+            "beancount-sort",
+            &mut io::stdout(),
+        );
+        return Ok(());
+    }
+    let current_dir = env::current_dir();
+    info!("Current directory is {:?}", current_dir);
+    if let Some(manifest_path) = &args.manifest {
+        let manifest = Manifest::load(manifest_path)?;
+        for (i, file) in manifest.file.iter().enumerate() {
+            info!(
+                "Sorting file {} of {} in the manifest",
+                i + 1,
+                manifest.file.len()
+            );
+            sort_file(&Manifest::file_args(file, &args))
+                .with_context(|| format!("unable to sort '{}'", file.path.display()))?;
+        }
+        return Ok(());
+    }
+    sort_file(&args)
 }
 
-fn find_entries(mut ledger_file: LedgerFile, n_skip: usize) -> Result<LedgerFile> {
-    let reader = BufReader::new(&ledger_file.file);
-    let mut lines = reader.lines();
-    let mut line_vec: Vec<(String, Line)> = Vec::new();
-    for _i in 0..n_skip {
-        let line: String = lines
-            .next()
-            .context("skipped more lines than are available in the file")??;
-        let entry = Entry {
-            content: line,
-            date: NaiveDate::from_ymd(1990, 1, 1),
-            entry_type: EntryType::Header,
+/// Sorts the files given by `args` and writes the result.
+fn sort_file(args: &Cli) -> Result<()> {
+    // keep stdout clean if the sorted file or the diff is written to it
+    let writes_stdout = args.out.as_deref() == Some(Path::new(STDOUT_PATH)) || args.dry_run;
+    let status = |message: String| {
+        if writes_stdout {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    };
+    for file in &args.file {
+        let name = if file == Path::new(STDIN_PATH) {
+            Path::new(STDIN_NAME)
+        } else {
+            file
         };
-        ledger_file.entries.push(entry)
+        status(format!("Selected beancount file is {:?}", name));
     }
-
-    for (mut nn, line) in lines.enumerate() {
-        nn += 1;
-        let n = nn + n_skip;
-        let line: String = line?;
-        let line_type: Line = get_line_type(&line, &n)?;
-        line_vec.push((line.clone(), line_type.clone()));
-        let mut entry: Entry = match line_type {
-            // If line has a date: create a dated entry
-            Line::Date(d) => construct_dated_entry(&line, d)?,
-            // If line is an option: create an entry with default date
-            Line::Option => Entry {
-                content: line.to_owned(),
-                date: NaiveDate::from_ymd(1990, 1, 1),
-                entry_type: EntryType::Option,
-            },
-            // If line is a section heading: ignore it
-            Line::Section => continue,
-            // If line is a comment: create an entry with default date
-            Line::Comment => Entry {
-                content: line.to_owned(),
-                date: NaiveDate::from_ymd(1990, 1, 1),
-                entry_type: EntryType::Comment,
-            },
-            // If line is an indented line: create an entry with default date
-            Line::Indent => Entry {
-                content: line.to_owned(),
-                date: NaiveDate::from_ymd(1990, 1, 1),
-                entry_type: EntryType::Indented,
-            },
-            // If line is an indented line: ignore it
-            Line::Empty => continue,
-        };
-        // If the line is a Comment then add it to the content of the previous Entry
-        if ledger_file
-            .entries
-            .last()
-            .filter(|e| e.entry_type == EntryType::Comment)
-            .is_some()
-        {
-            let comment_entry = ledger_file.entries.pop().unwrap(); // unwrap is save because it was already checked that there is a values
-            entry.content = comment_entry.content + "\n" + &entry.content;
+    if args.section_deco_count == 0 {
+        return Err(anyhow!("--section-deco-count has to be at least 1"));
+    }
+    if !is_deco_char(args.section_deco) {
+        return Err(anyhow!(
+            "--section-deco can't be a letter, a space, a quote or ';', got {:?}",
+            args.section_deco
+        ));
+    }
+    let section_deco = SectionDeco {
+        deco: args.section_deco,
+        count: args.section_deco_count,
+        width: args.deco_width,
+    };
+    let parse_options = ParseOptions {
+        skipn: args.skipn,
+        strict_meta_blocks: args.strict_meta_blocks,
+        follow_includes: args.follow_includes,
+        orphan_comments: args.orphan_comments,
+        comments_follow: args.comments_follow,
+        keep_leading_comments: args.keep_leading_comments,
+        lenient_dates: args.lenient_dates,
+        keep_org_headings: args.keep_org_headings,
+        keep_sections: args.keep_sections,
+        lenient: args.lenient,
+        section_deco: section_deco.clone(),
+        encoding: parse_encoding(&args.encoding)?,
+    };
+    let mut sort_options = SortOptions {
+        custom_section: args.custom_section,
+        notes_section: args.notes_section,
+        queries_section: args.queries_section,
+        plugins_with_options: args.plugins_with_options,
+        tiebreak: args.same_date_order.unwrap_or(args.tiebreak),
+        descending: args.sort_desc,
+        reverse: args.reverse,
+        interleave_balances: args.interleave_balances,
+        month_headers: args.month_headers,
+        keep_sections: args.keep_sections,
+        no_headings: args.no_headings,
+        always_show_sections: args.always_show_sections,
+        section_deco,
+        filter_sections: args.filter_section.clone(),
+        accounts_order: args.accounts_order,
+        commodities_order: args.commodities_order,
+        prices_order: args.prices_order,
+        ..Default::default()
+    };
+    if let Some(section_order) = &args.section_order {
+        let (mut section_order, missing) = parse_section_order(section_order)?;
+        // sections that aren't written anyway, e.g. Custom without --custom-section, aren't worth a warning
+        let missing_written: Vec<&str> = missing
+            .iter()
+            .map(|s| s.as_str())
+            .filter(|s| is_written(s, &sort_options))
+            .collect();
+        if let Some(fallback) = &args.fallback_section {
+            section_order.retain(|s| !missing.contains(s));
+            if !missing_written.is_empty() {
+                eprintln!(
+                    "Warning: Entries of the sections missing in --section-order are sorted into {}: {}",
+                    fallback,
+                    missing_written.join(", ")
+                );
+            }
+        } else if !missing_written.is_empty() {
+            eprintln!(
+                "Warning: Sections missing in --section-order are appended: {}",
+                missing_written.join(", ")
+            );
+        }
+        sort_options.section_order = section_order;
+        sort_options.fallback_section = args.fallback_section.clone();
+    }
+    if args
+        .file
+        .iter()
+        .filter(|f| *f == Path::new(STDIN_PATH))
+        .count()
+        > 1
+    {
+        return Err(anyhow!("stdin (-) can only be given once as --file"));
+    }
+    if args.dry_run || args.check {
+        if args.reads_stdin() {
+            return Err(anyhow!(
+                "--dry-run and --check need the original file and can't read from stdin"
+            ));
+        }
+        let ledger_file = sort_ledger_files(args, &parse_options, &sort_options)?;
+        let mut original = String::new();
+        for file in &args.file {
+            match parse_options.encoding {
+                Some(encoding) => original.push_str(&decode(&fs::read(file)?, encoding, file)?),
+                None => original.push_str(&fs::read_to_string(file)?),
+            }
         }
-        // If the line is indented and the last entry was either a Transaction or a Commodity then add its content to the previous Entrys content
-        if let EntryType::Indented = entry.entry_type {
-            let last_entry = ledger_file
-                .entries
-                .pop()
-                .context(format!("Missplaced indented line: Line {}", n))?;
-            // continue only if last line was a MultiLine-Entry
-            if let EntryType::Transaction | EntryType::Commodity = last_entry.entry_type {
-                let content_new = last_entry.content.to_owned() + "\n" + &entry.content;
-                let new_entry = Entry {
-                    content: content_new,
-                    date: last_entry.date,
-                    entry_type: last_entry.entry_type,
-                };
-                ledger_file.entries.push(new_entry);
-            } else {
+        let sorted = ledger_file.format_ledger_file(args.blank_lines());
+        if args.dry_run {
+            if args.file.len() > 1 && args.diff_format == Some(DiffFormat::Patch) {
                 return Err(anyhow!(
-                    "Misplaced indented line: Line {}\n\"{}\"",
-                    n,
-                    entry.content
+                    "--diff-format patch needs a single --file because the merged files can't be patched"
                 ));
-            };
-        } else {
-            ledger_file.entries.push(entry.clone())
-        };
+            }
+            print!(
+                "{}",
+                format_diff(
+                    &original,
+                    &sorted,
+                    &args.file[0],
+                    args.diff_format.unwrap_or_default()
+                )
+            );
+        }
+        if args.check {
+            if original != sorted {
+                eprintln!("{} is not sorted.", args.file[0].display());
+                process::exit(1);
+            }
+            println!("{} is already sorted.", args.file[0].display());
+        }
+        return Ok(());
     }
-    Ok(ledger_file)
-}
-
-fn get_section_variant(entry: &str) -> Result<EntryType> {
-    //["Header", "Accounts", "Options", "Commodities", "Other Entries", "Prices", "Transactions"]
-    let entry_type = match entry {
-        "Accounts" => EntryType::Account,
-        "Options" => EntryType::Option,
-        "Commodities" => EntryType::Commodity,
-        "Other Entries" => EntryType::OtherEntry,
-        "Prices" => EntryType::Price,
-        "Transactions" => EntryType::Transaction,
-        "Header" => EntryType::Header,
-        _ => return Err(anyhow!("Not handled Section Type \"{}\"", entry)),
-    };
-    Ok(entry_type)
+    let out_path = args.out_path()?;
+    if args.assert_sorted && (args.output_format == "json" || out_path == Path::new(STDOUT_PATH)) {
+        return Err(anyhow!(
+            "--assert-sorted has to read the sorted beancount file again and can't be used with --output-format json or --out -"
+        ));
+    }
+    if args.split_by_year.is_some() && args.output_format == "json" {
+        return Err(anyhow!(
+            "--split-by-year writes beancount files and can't be used with --output-format json"
+        ));
+    }
+    if !args.no_backup {
+        // there is nothing to back up for stdin
+        for file in args.file.iter().filter(|f| *f != Path::new(STDIN_PATH)) {
+            let backup = backup_file(file, args.backup_dir.as_deref())?;
+            status(format!(
+                "Backup done: {} -> {}",
+                file.display(),
+                backup.display()
+            ));
+        }
+    }
+    let ledger_file = sort_ledger_files(args, &parse_options, &sort_options)?;
+    if args.assert_sorted && args.split_by_year.is_none() {
+        assert_sorted(&ledger_file, out_path, args, &parse_options, &sort_options)?;
+    }
+    if out_path == Path::new(STDOUT_PATH) {
+        write_sorted(ledger_file, open_output(out_path)?, out_path, args, &status)?;
+    } else {
+        if !args.in_place && args.file.iter().any(|f| is_same_file(f, out_path)) {
+            status(format!(
+                "{} is an input file and is replaced by the sorted file",
+                out_path.display()
+            ));
+        }
+        // the file is only replaced once the sorted file is written completely,
+        // a failed write leaves it untouched. A symlink is followed to keep it a symlink.
+        let target = out_path
+            .canonicalize()
+            .unwrap_or_else(|_| out_path.to_path_buf());
+        write_atomically(&target, |out| {
+            write_sorted(ledger_file, out, out_path, args, &status)
+        })?;
+    }
+    Ok(())
 }
 
-/// Sorts a [Vec] of [Entry] by their date and their section
-fn sort_entries(mut entries: Vec<Entry>) -> Result<Vec<Entry>> {
-    entries.sort_by_key(|e| e.date);
-    let mut sorted_entries: Vec<Entry> = Vec::new();
-    let deco = DECO.repeat(NDECO);
-    for section in SECTIONS {
-        // create a new entry with the section heading like:
-        // ;€€€€€€€€€€€€€€€\n;€€€€Options€€€€\n;€€€€€€€€€€€€€€€
-        if section != "Header" {
-            let section_string: String = {
-                ";".to_string()
-                    + &deco.clone()
-                    + &DECO.repeat(section.len())
-                    + &deco
-                    + "\n"
-                    + ";"
-                    + &deco
-                    + section
-                    + &deco
-                    + "\n"
-                    + ";"
-                    + &deco
-                    + &DECO.repeat(section.len())
-                    + &deco
-            };
-            let section_entry = Entry {
-                content: section_string,
-                date: NaiveDate::from_ymd(1990, 1, 1),
-                entry_type: EntryType::Section,
-            };
-            sorted_entries.push(section_entry);
+/// Writes the sorted `ledger_file` to `out` in the format given by `args`.
+/// `out_path` is only used in messages.
+fn write_sorted(
+    ledger_file: LedgerFile,
+    mut out: Box<dyn Write>,
+    out_path: &Path,
+    args: &Cli,
+    status: &dyn Fn(String),
+) -> Result<()> {
+    if let Some(dir) = &args.split_by_year {
+        fs::create_dir_all(dir).with_context(|| format!("unable to create '{}'", dir.display()))?;
+        for path in ledger_file.write_split_by_year(out, dir, args.blank_lines())? {
+            status(format!("Written {}", path.display()));
         }
-        let section_variant = get_section_variant(section)?;
-        let entries_iter = entries.iter();
-        entries_iter
-            .filter(|e| mem::discriminant(&e.entry_type) == mem::discriminant(&section_variant))
-            .for_each(|entry| sorted_entries.push(entry.to_owned()))
+    } else if args.output_format == "json" {
+        out.write_all(ledger_file.format_json()?.as_bytes())
+            .and_then(|_| out.flush())
+            .with_context(|| format!("unable to write '{}'", out_path.display()))?;
+    } else {
+        ledger_file.write_ledger_file(out, args.blank_lines())?;
     }
-    Ok(sorted_entries)
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    let args = Cli::from_args();
-    let current_dir = env::current_dir();
-    info!("Current directory is {:?}", current_dir);
-    println!("Selected beancount file is {:?}", &args.file);
-    let mut ledger_file = read_file(&args.file)?;
-    backup_file(&args.file)?;
-    ledger_file = find_entries(ledger_file, args.skipn)?;
-    ledger_file.entries = sort_entries(ledger_file.entries)?;
-    ledger_file.write_ledger_file(&args.out, &args.spaces)?;
+/// Parses the sorted `ledger_file` as it is written to `path` and sorts it once more,
+/// before the file at `path` is replaced. A difference means that the sorted file isn't
+/// parsed back the way it was written, the diff is printed to stderr.
+fn assert_sorted(
+    ledger_file: &LedgerFile,
+    path: &Path,
+    args: &Cli,
+    parse_options: &ParseOptions,
+    sort_options: &SortOptions,
+) -> Result<()> {
+    let written = ledger_file.format_ledger_file(args.blank_lines());
+    let written_file = LedgerFile {
+        path: path.to_path_buf(),
+        file: Input::Decoded(io::Cursor::new(written.clone().into_bytes())),
+        entries: Vec::new(),
+        bom: false,
+        line_ending: ledger_file.line_ending,
+        encoding: None,
+    };
+    // the sorted content is still UTF-8, it is only transcoded when it is written
+    let parse_options = ParseOptions {
+        encoding: None,
+        ..parse_options.clone()
+    };
+    let mut ledger_file = find_entries(written_file, &parse_options)?;
+    // out of range entries are appended unsorted like in sort_ledger_files
+    let (entries, mut out_of_range) =
+        split_by_date_range(ledger_file.entries, args.date_from, args.date_to);
+    ledger_file.entries = sort_entries(entries, sort_options)?;
+    ledger_file.entries.append(&mut out_of_range);
+    let resorted = ledger_file.format_ledger_file(args.blank_lines());
+    if resorted != written {
+        eprint!("{}", unified_diff(&written, &resorted, path));
+        return Err(anyhow!(
+            "Sorting '{}' again changes it, see the diff above. Please report this as a bug.",
+            path.display()
+        ));
+    }
+    info!("{} is still sorted after reading it again", path.display());
     Ok(())
 }
 
 #[cfg(test)]
 mod test {
-    use std::mem::discriminant;
-
     use super::*;
 
-    // TODO write setup struct
-    struct Setup {
-        good_entry: Entry,
-        bad_entry: Entry,
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+        let args = Cli::parse_from([
+            "beancount-sort",
+            "-f",
+            "2021.beancount",
+            "-f",
+            "2022.beancount",
+            "-s",
+            "2",
+            "--orphan-comments",
+            "attach-prev",
+            "--tiebreak",
+            "stable",
+            "--line-ending",
+            "crlf",
+            "-vv",
+        ]);
+        assert_eq!(args.file.len(), 2);
+        assert_eq!(args.skipn, 2);
+        assert_eq!(args.orphan_comments, OrphanComments::AttachPrev);
+        assert_eq!(args.tiebreak, TieBreak::Stable);
+        assert_eq!(args.line_ending, "crlf");
+        assert_eq!(args.verbose, 2);
+        assert!(
+            Cli::try_parse_from(["beancount-sort", "-f", "a", "--tiebreak", "random"]).is_err()
+        );
+        let args = Cli::parse_from(["beancount-sort", "-f", "a", "--same-date-order", "input"]);
+        assert_eq!(args.same_date_order, Some(TieBreak::Stable));
+        assert!(Cli::try_parse_from([
+            "beancount-sort",
+            "-f",
+            "a",
+            "--same-date-order",
+            "payee",
+            "--tiebreak",
+            "amount"
+        ])
+        .is_err());
+        assert!(Cli::try_parse_from([
+            "beancount-sort",
+            "-f",
+            "a",
+            "--no-backup",
+            "--backup-dir",
+            "backups"
+        ])
+        .is_err());
+        let args = Cli::parse_from(["beancount-sort", "-f", "a", "--blank-lines", "sections"]);
+        assert_eq!(args.blank_lines(), BlankLines::Sections);
+        let args = Cli::parse_from(["beancount-sort", "-f", "a", "--spaces"]);
+        assert_eq!(args.blank_lines(), BlankLines::Entries);
+        assert!(Cli::try_parse_from([
+            "beancount-sort",
+            "-f",
+            "a",
+            "--spaces",
+            "--blank-lines",
+            "none"
+        ])
+        .is_err());
     }
 
-    impl Setup {
-        fn new() -> Self {
-            let good_line: &str = "2022-04-17 * \"Schlosspark Pankow\" \"Brezel \"";
-            let good_date: NaiveDate = NaiveDate::from_ymd(2022, 01, 01);
-            Self {
-                good_entry: Entry {
-                    content: good_line.to_string(),
-                    date: good_date,
-                    entry_type: EntryType::Transaction,
-                },
-                bad_entry: Entry {
-                    content: good_line.to_string(),
-                    date: good_date,
-                    // wrong entry type
-                    entry_type: EntryType::Account,
-                },
-            }
-        }
+    #[test]
+    fn test_out_path() {
+        let args = Cli::parse_from([
+            "beancount-sort",
+            "-f",
+            "in.beancount",
+            "-o",
+            "out.beancount",
+        ]);
+        assert_eq!(args.out_path().unwrap(), Path::new("out.beancount"));
+        let args = Cli::parse_from(["beancount-sort", "-f", "in.beancount", "--in-place"]);
+        assert_eq!(args.out_path().unwrap(), Path::new("in.beancount"));
+        let args = Cli::parse_from([
+            "beancount-sort",
+            "-f",
+            "in.beancount",
+            "-o",
+            "out.beancount",
+            "--in-place",
+        ]);
+        assert!(args.out_path().is_err());
+        let args = Cli::parse_from(["beancount-sort", "-f", "in.beancount"]);
+        assert!(args.out_path().is_err());
+        let args = Cli::parse_from(["beancount-sort", "-f", "-", "--in-place"]);
+        assert!(args.out_path().is_err());
+        let args = Cli::parse_from([
+            "beancount-sort",
+            "-f",
+            "2021.beancount",
+            "-f",
+            "2022.beancount",
+            "--in-place",
+        ]);
+        assert!(args.out_path().is_err());
     }
 
     #[test]
-    fn test_get_section_variant() {
-        assert_eq!(
-            discriminant(&get_section_variant("Header").unwrap()),
-            discriminant(&EntryType::Header)
-        );
-        assert_eq!(
-            discriminant(&get_section_variant("Accounts").unwrap()),
-            discriminant(&EntryType::Account)
-        );
-        assert_eq!(
-            discriminant(&get_section_variant("Options").unwrap()),
-            discriminant(&EntryType::Option)
-        );
-        assert_eq!(
-            discriminant(&get_section_variant("Commodities").unwrap()),
-            discriminant(&EntryType::Commodity)
-        );
-        assert_eq!(
-            discriminant(&get_section_variant("Other Entries").unwrap()),
-            discriminant(&EntryType::OtherEntry)
-        );
-        assert_eq!(
-            discriminant(&get_section_variant("Prices").unwrap()),
-            discriminant(&EntryType::Price)
-        );
-        assert_eq!(
-            discriminant(&get_section_variant("Transactions").unwrap()),
-            discriminant(&EntryType::Transaction)
-        );
-        assert!(get_section_variant("abcdefg").is_err());
+    fn test_config() {
+        let config: Config = toml::from_str(EXAMPLE_CONFIG).unwrap();
+        assert_eq!(config.tiebreak, Some(TieBreak::Date));
+        assert!(toml::from_str::<Config>("unknown-key = true").is_err());
+        assert!(toml::from_str::<Config>("tiebreak = \"random\"").is_err());
+
+        let argv = [
+            "beancount-sort",
+            "-f",
+            "in.beancount",
+            "--skipn",
+            "1",
+            "--tiebreak",
+            "date",
+        ];
+        let config: Config = toml::from_str(
+            "skipn = 3\n\
+             spaces = true\n\
+             tiebreak = \"stable\"\n\
+             orphan-comments = \"attach-next\"\n\
+             section-order = \"Prices\"\n",
+        )
+        .unwrap();
+        let matches = Cli::command().get_matches_from(argv);
+        let mut args = Cli::from_arg_matches(&matches).unwrap();
+        config.apply(&mut args, &matches);
+        assert_eq!(args.skipn, 1);
+        assert_eq!(args.tiebreak, TieBreak::Date);
+        assert!(args.spaces);
+        assert_eq!(args.orphan_comments, OrphanComments::AttachNext);
+        assert_eq!(args.section_order.as_deref(), Some("Prices"));
+        assert_eq!(args.line_ending, "auto");
     }
+
     #[test]
-    fn test_sort_entries() {
-        let entries = vec![
-            Entry {
-                content: "3".to_string(),
-                date: NaiveDate::from_ymd(2021, 01, 01),
-                entry_type: EntryType::Transaction,
-            },
-            Entry {
-                content: "1".to_string(),
-                date: NaiveDate::from_ymd(2021, 01, 02),
-                entry_type: EntryType::Option,
-            },
-            Entry {
-                content: "2".to_string(),
-                date: NaiveDate::from_ymd(2021, 01, 03),
-                entry_type: EntryType::Account,
-            },
-        ];
-        let mut sorted_entries_function = sort_entries(entries).unwrap();
-        let sorted_entries_manual = [
-            Entry {
-                content: "1".to_string(),
-                date: NaiveDate::from_ymd(2021, 01, 02),
-                entry_type: EntryType::Option,
-            },
-            Entry {
-                content: "2".to_string(),
-                date: NaiveDate::from_ymd(2021, 01, 03),
-                entry_type: EntryType::Account,
-            },
-            Entry {
-                content: "3".to_string(),
-                date: NaiveDate::from_ymd(2021, 01, 01),
-                entry_type: EntryType::Transaction,
-            },
-        ];
-        let mut i = 0;
-        while i < sorted_entries_function.len() {
-            if mem::discriminant(&sorted_entries_function[i].entry_type)
-                == mem::discriminant(&EntryType::Section)
-            {
-                sorted_entries_function.remove(i);
-            } else {
-                i += 1;
-            }
-        }
-        // const SECTIONS: [&str; 7] = ["Header", "Options", "Accounts", "Commodities", "Other Entries", "Prices", "Transactions"];
-        assert_eq!(
-            sorted_entries_function[0].content,
-            sorted_entries_manual[0].content
-        );
+    fn test_manifest() {
+        let manifest: Manifest = toml::from_str(
+            "[[file]]\n\
+             path = \"2021.beancount\"\n\
+             skipn = 1\n\
+             [[file]]\n\
+             path = \"accounts.beancount\"\n\
+             out = \"accounts.sorted.beancount\"\n\
+             section-order = \"Accounts\"\n",
+        )
+        .unwrap();
+        assert!(toml::from_str::<Manifest>("[[file]]\nskipn = 1\n").is_err());
+        let args = Cli::parse_from(["beancount-sort", "--manifest", "ledger.toml", "-s", "2"]);
+        let first = Manifest::file_args(&manifest.file[0], &args);
+        assert_eq!(first.file, [PathBuf::from("2021.beancount")]);
+        assert_eq!(first.skipn, 1);
+        assert_eq!(first.out_path().unwrap(), Path::new("2021.beancount"));
+        let second = Manifest::file_args(&manifest.file[1], &args);
+        assert_eq!(second.skipn, 2);
         assert_eq!(
-            sorted_entries_function[1].content,
-            sorted_entries_manual[1].content
+            second.out_path().unwrap(),
+            Path::new("accounts.sorted.beancount")
         );
-        assert_eq!(
-            sorted_entries_function[2].content,
-            sorted_entries_manual[2].content
+        assert_eq!(second.section_order.as_deref(), Some("Accounts"));
+        assert!(
+            Cli::try_parse_from(["beancount-sort", "--manifest", "ledger.toml", "-f", "a"])
+                .is_err()
         );
     }
-    #[test]
-    fn test_construct_dated_entry() {
-        let good_line: &str = "2022-04-17 * \"Schlosspark Pankow\" \"Brezel \"";
-        let good_date: NaiveDate = NaiveDate::from_ymd(2022, 01, 01);
-        let constructed_entry: Entry = construct_dated_entry(good_line, good_date).unwrap();
-        let good_entry: Entry = Entry {
-            content: good_line.to_string(),
-            date: good_date,
-            entry_type: EntryType::Transaction,
-        };
-        assert_eq!(constructed_entry, good_entry);
-    }
 }
@@ -0,0 +1,550 @@
+use beancount_sort::{
+    find_entries, open_output, parse_encoding, read_file, remove_duplicate_options, sort_entries,
+    BlankLines, EntryType, LineEnding, OrphanComments, ParseOptions, SectionDeco, SortOptions, BOM,
+    DECO, NDECO,
+};
+use std::{
+    env, fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+const UNSORTED: &str = "\
+2021-01-20 * \"Direkthandel\" \"Aktienkauf\"
+    Assets:Stock                                   1 GME {69.420 EUR}
+    Assets:Giro
+option \"operating_currency\" \"EUR\"
+2021-01-21 price GME                                420.69 EUR
+2021-01-01 open Assets:Giro   EUR
+2002-01-01 commodity EUR
+    name: \"Euro\"
+";
+
+/// Writes `content` to a file in the temporary directory and returns its path.
+fn write_temp_file(name: &str, content: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("beancount-sort_{}", name));
+    fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn sorts_file_end_to_end() {
+    let in_path = write_temp_file("end_to_end_in.beancount", UNSORTED);
+    let out_path = env::temp_dir().join("beancount-sort_end_to_end_out.beancount");
+    let mut ledger_file =
+        find_entries(read_file(&in_path).unwrap(), &ParseOptions::default()).unwrap();
+    ledger_file.entries = sort_entries(ledger_file.entries, &SortOptions::default()).unwrap();
+    ledger_file
+        .write_ledger_file(open_output(&out_path).unwrap(), BlankLines::None)
+        .unwrap();
+    let sorted = fs::read_to_string(&out_path).unwrap();
+    fs::remove_file(&in_path).unwrap();
+    fs::remove_file(&out_path).unwrap();
+
+    let deco = DECO.repeat(NDECO);
+    let position = |needle: &str| sorted.find(needle).unwrap();
+    assert!(position(&format!(";{}Options{}", deco, deco)) < position("option \"operating"));
+    assert!(position("option \"operating") < position("2021-01-01 open"));
+    assert!(position("2021-01-01 open") < position("2002-01-01 commodity EUR\n    name"));
+    assert!(position("2002-01-01 commodity") < position("2021-01-21 price"));
+    assert!(position("2021-01-21 price") < position("2021-01-20 * \"Direkthandel\""));
+    assert!(sorted.contains("2021-01-20 * \"Direkthandel\" \"Aktienkauf\"\n    Assets:Stock"));
+}
+
+#[test]
+fn keeps_skipped_lines_at_the_top() {
+    let path = write_temp_file(
+        "skipped_lines.beancount",
+        ";; -*- mode: beancount -*-\n2021-01-01 open Assets:Giro   EUR\n",
+    );
+    let ledger_file = find_entries(
+        read_file(&path).unwrap(),
+        &ParseOptions {
+            skipn: 1,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    fs::remove_file(&path).unwrap();
+    let entries = sort_entries(ledger_file.entries, &SortOptions::default()).unwrap();
+    assert_eq!(entries[0].entry_type, EntryType::Header);
+    assert_eq!(entries[0].content, ";; -*- mode: beancount -*-");
+}
+
+#[test]
+fn merges_multiple_files() {
+    let path_2021 = write_temp_file(
+        "merge_2021.beancount",
+        "option \"operating_currency\" \"EUR\"\n2021-03-01 * \"Shop\"\n  Expenses:Food  1 EUR\n  Assets:Giro\n2021-01-01 open Assets:Giro EUR\n",
+    );
+    let path_2022 = write_temp_file(
+        "merge_2022.beancount",
+        "option \"operating_currency\" \"EUR\"\noption \"operating_currency\" \"USD\"\n2022-01-01 open Expenses:Food EUR\n2021-02-01 * \"Shop\"\n  Expenses:Food  1 EUR\n  Assets:Giro\n",
+    );
+    let mut ledger_file =
+        find_entries(read_file(&path_2021).unwrap(), &ParseOptions::default()).unwrap();
+    let mut other_file =
+        find_entries(read_file(&path_2022).unwrap(), &ParseOptions::default()).unwrap();
+    fs::remove_file(&path_2021).unwrap();
+    fs::remove_file(&path_2022).unwrap();
+    ledger_file.entries.append(&mut other_file.entries);
+
+    let duplicates = remove_duplicate_options(&mut ledger_file.entries);
+    assert_eq!(duplicates.len(), 1);
+    assert_eq!(
+        duplicates[0].content,
+        "option \"operating_currency\" \"EUR\""
+    );
+
+    let entries = sort_entries(ledger_file.entries, &SortOptions::default()).unwrap();
+    let heading = format!(";{}Transactions", DECO.repeat(NDECO));
+    let headings = entries
+        .iter()
+        .filter(|e| e.content.contains(&heading))
+        .count();
+    assert_eq!(headings, 1);
+    let first_lines: Vec<&str> = entries
+        .iter()
+        .filter(|e| e.entry_type != EntryType::Section)
+        .map(|e| e.content.lines().next().unwrap())
+        .collect();
+    assert_eq!(
+        first_lines,
+        [
+            "option \"operating_currency\" \"EUR\"",
+            "option \"operating_currency\" \"USD\"",
+            "2021-01-01 open Assets:Giro EUR",
+            "2022-01-01 open Expenses:Food EUR",
+            "2021-02-01 * \"Shop\"",
+            "2021-03-01 * \"Shop\"",
+        ]
+    );
+}
+
+#[test]
+fn sorting_a_sorted_file_changes_nothing() {
+    let in_path = write_temp_file("idempotent_in.beancount", UNSORTED);
+    let mut ledger_file =
+        find_entries(read_file(&in_path).unwrap(), &ParseOptions::default()).unwrap();
+    ledger_file.entries = sort_entries(ledger_file.entries, &SortOptions::default()).unwrap();
+    let sorted = ledger_file.format_ledger_file(BlankLines::None);
+    let sorted_path = write_temp_file("idempotent_sorted.beancount", &sorted);
+    let mut ledger_file =
+        find_entries(read_file(&sorted_path).unwrap(), &ParseOptions::default()).unwrap();
+    ledger_file.entries = sort_entries(ledger_file.entries, &SortOptions::default()).unwrap();
+    fs::remove_file(&in_path).unwrap();
+    fs::remove_file(&sorted_path).unwrap();
+    assert_eq!(ledger_file.format_ledger_file(BlankLines::None), sorted);
+}
+
+#[test]
+fn keeps_comments_at_the_end_of_the_file() {
+    let inputs = [
+        format!(
+            "{}; reconciled until here\n;; TODO: import February\n",
+            UNSORTED
+        ),
+        format!("{}\n; reconciled until here\n\n", UNSORTED),
+        "; nothing booked yet\n".to_string(),
+    ];
+    for (i, input) in inputs.iter().enumerate() {
+        let path = write_temp_file(&format!("trailing_comments_{}.beancount", i), input);
+        for orphan_comments in [
+            OrphanComments::Keep,
+            OrphanComments::AttachNext,
+            OrphanComments::AttachPrev,
+        ] {
+            let options = ParseOptions {
+                orphan_comments,
+                ..Default::default()
+            };
+            let mut ledger_file = find_entries(read_file(&path).unwrap(), &options).unwrap();
+            ledger_file.entries =
+                sort_entries(ledger_file.entries, &SortOptions::default()).unwrap();
+            let sorted = ledger_file.format_ledger_file(BlankLines::None);
+            let non_empty_lines = |text: &str| {
+                text.lines()
+                    .filter(|l| !l.trim().is_empty() && !l.starts_with(&format!(";{}", DECO)))
+                    .count()
+            };
+            assert_eq!(non_empty_lines(&sorted), non_empty_lines(input));
+            assert!(sorted.contains("; "));
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+#[test]
+fn sorting_descending_can_be_undone() {
+    let in_path = write_temp_file(
+        "descending_in.beancount",
+        &format!(
+            "{}2021-02-01 price GME 40.00 EUR\n2021-01-05 * \"Bakery\"\n    Expenses:Food  3 EUR\n    Assets:Giro\n",
+            UNSORTED
+        ),
+    );
+    let desc_path = env::temp_dir().join("beancount-sort_descending_desc.beancount");
+    let sort = |path: &PathBuf, descending: bool| {
+        let mut ledger_file =
+            find_entries(read_file(path).unwrap(), &ParseOptions::default()).unwrap();
+        let options = SortOptions {
+            descending,
+            ..Default::default()
+        };
+        ledger_file.entries = sort_entries(ledger_file.entries, &options).unwrap();
+        ledger_file
+    };
+    let ascending = sort(&in_path, false).format_ledger_file(BlankLines::None);
+    sort(&in_path, true)
+        .write_ledger_file(open_output(&desc_path).unwrap(), BlankLines::None)
+        .unwrap();
+    let descending = fs::read_to_string(&desc_path).unwrap();
+    let resorted = sort(&desc_path, false).format_ledger_file(BlankLines::None);
+    fs::remove_file(&in_path).unwrap();
+    fs::remove_file(&desc_path).unwrap();
+
+    let deco = DECO.repeat(NDECO);
+    let position = |needle: &str| descending.find(needle).unwrap();
+    assert!(position(&format!(";{}Prices{}", deco, deco)) < position("2021-02-01 price"));
+    assert!(position("2021-02-01 price") < position("2021-01-21 price"));
+    assert!(position("2021-01-20 *") < position("2021-01-05 *"));
+    assert_ne!(descending, ascending);
+    assert_eq!(resorted, ascending);
+}
+
+#[test]
+fn strips_and_keeps_a_byte_order_mark() {
+    let path = write_temp_file(
+        "bom.beancount",
+        &format!("{};; exported by Windows\n{}", BOM, UNSORTED),
+    );
+    let mut ledger_file =
+        find_entries(read_file(&path).unwrap(), &ParseOptions::default()).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(ledger_file.bom);
+    assert!(ledger_file.entries[0]
+        .content
+        .starts_with(";; exported by Windows\n2021-01-20 *"));
+    ledger_file.entries = sort_entries(ledger_file.entries, &SortOptions::default()).unwrap();
+    assert!(ledger_file
+        .format_ledger_file(BlankLines::None)
+        .starts_with(BOM));
+    ledger_file.bom = false;
+    assert!(!ledger_file
+        .format_ledger_file(BlankLines::None)
+        .contains(BOM));
+}
+
+#[test]
+fn keeps_crlf_line_endings() {
+    let path = write_temp_file(
+        "crlf.beancount",
+        &format!("{}\n; opened online\n", UNSORTED).replace('\n', "\r\n"),
+    );
+    let mut ledger_file =
+        find_entries(read_file(&path).unwrap(), &ParseOptions::default()).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(ledger_file.line_ending, LineEnding::Crlf);
+    assert!(ledger_file
+        .entries
+        .iter()
+        .all(|e| !e.content.contains('\r')));
+    ledger_file.entries = sort_entries(ledger_file.entries, &SortOptions::default()).unwrap();
+    let sorted = ledger_file.format_ledger_file(BlankLines::None);
+    assert!(sorted.ends_with("\r\n"));
+    assert_eq!(sorted.matches('\n').count(), sorted.matches("\r\n").count());
+    assert!(!sorted.contains("\r\r"));
+
+    ledger_file.line_ending = LineEnding::Lf;
+    assert!(!ledger_file
+        .format_ledger_file(BlankLines::None)
+        .contains('\r'));
+}
+
+/// Runs beancount-sort with `args` and writes `input` to its stdin.
+fn run_with_stdin(args: &[&str], input: &str) -> std::process::Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_beancount-sort"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn reads_from_stdin() {
+    let out_path = env::temp_dir().join("beancount-sort_stdin_out.beancount");
+    let output = run_with_stdin(
+        &["--file", "-", "--out", out_path.to_str().unwrap()],
+        UNSORTED,
+    );
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Selected beancount file is \"<stdin>\"")
+    );
+    let sorted = fs::read_to_string(&out_path).unwrap();
+    fs::remove_file(&out_path).unwrap();
+    assert!(sorted.find("2021-01-01 open").unwrap() < sorted.find("2021-01-21 price").unwrap());
+
+    let output = run_with_stdin(
+        &["--file", "-", "--out", out_path.to_str().unwrap()],
+        "2021-01-01 open Assets:Giro\nnot beancount\n",
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("<stdin>:2: "));
+    assert!(!out_path.exists());
+
+    let output = run_with_stdin(&["--file", "-", "--in-place"], UNSORTED);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn sorts_in_place() {
+    let dir = env::temp_dir().join("beancount-sort_in_place");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("finances.beancount");
+    fs::write(&path, UNSORTED).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_beancount-sort"))
+        .args(["--in-place", "--file"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let sorted = fs::read_to_string(&path).unwrap();
+    let mut files: Vec<String> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|f| f.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(sorted.find("2021-01-01 open").unwrap() < sorted.find("2021-01-21 price").unwrap());
+    // the temporary file is renamed over the input, only the backup is left
+    assert_eq!(files, ["finances.beancount", "finances_backup.beancount"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn replaces_the_input_given_as_out() {
+    let dir = env::temp_dir().join("beancount-sort_out_is_input");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("finances.beancount");
+    let link = dir.join("link.beancount");
+    std::os::unix::fs::symlink(&path, &link).unwrap();
+    for out in [&path, &link] {
+        fs::write(&path, UNSORTED).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_beancount-sort"))
+            .arg("--no-backup")
+            .arg("--file")
+            .arg(&path)
+            .arg("--out")
+            .arg(out)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        let sorted = fs::read_to_string(&path).unwrap();
+        assert!(sorted.find("2021-01-01 open").unwrap() < sorted.find("2021-01-21 price").unwrap());
+        assert!(sorted.contains("2021-01-20 * \"Direkthandel\" \"Aktienkauf\""));
+    }
+    let files = fs::read_dir(&dir).unwrap().count();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(files, 2);
+}
+
+#[test]
+fn writes_to_stdout() {
+    let in_path = write_temp_file("stdout_in.beancount", UNSORTED);
+    let output = Command::new(env!("CARGO_BIN_EXE_beancount-sort"))
+        .args(["--no-backup", "--out", "-", "--file"])
+        .arg(&in_path)
+        .output()
+        .unwrap();
+    let mut ledger_file =
+        find_entries(read_file(&in_path).unwrap(), &ParseOptions::default()).unwrap();
+    fs::remove_file(&in_path).unwrap();
+    ledger_file.entries = sort_entries(ledger_file.entries, &SortOptions::default()).unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        ledger_file.format_ledger_file(BlankLines::None)
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Selected beancount file"));
+}
+
+#[test]
+fn writes_backup_messages_to_stderr_with_stdout_output() {
+    let in_path = write_temp_file("stdout_backup.beancount", UNSORTED);
+    let backup_dir = env::temp_dir().join("beancount-sort_stdout_backups");
+    let output = Command::new(env!("CARGO_BIN_EXE_beancount-sort"))
+        .args(["--out", "-", "--backup-dir"])
+        .arg(&backup_dir)
+        .arg("--file")
+        .arg(&in_path)
+        .output()
+        .unwrap();
+    fs::remove_file(&in_path).unwrap();
+    fs::remove_dir_all(&backup_dir).unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("Backup done"));
+    assert!(stdout.starts_with(";"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Backup done"));
+}
+
+#[test]
+fn replaces_headings_of_another_decoration() {
+    let sort = |args: &[&str], input: &str| {
+        let output = run_with_stdin(&[&["--file", "-", "--out", "-"], args].concat(), input);
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let with_euro = sort(&[], UNSORTED);
+    let with_equals = sort(&["--deco-char", "=", "--deco-width", "30"], &with_euro);
+    assert!(!with_equals.contains(DECO));
+    assert_eq!(
+        with_equals,
+        sort(&["--deco-char", "=", "--deco-width", "30"], UNSORTED)
+    );
+    let with_dashes = sort(
+        &["--deco-char", "-", "--section-deco-count", "2"],
+        &with_equals,
+    );
+    assert!(!with_dashes.contains("=="));
+    assert_eq!(sort(&[], &with_dashes), with_euro);
+    assert_eq!(sort(&[], &with_euro), with_euro);
+}
+
+#[test]
+fn keeps_skipped_banner_lines() {
+    let content = ";==========\n;==Prices==\n2021-01-21 price GME   420.69 EUR\n";
+    let output = run_with_stdin(&["--file", "-", "--out", "-", "--skipn", "1"], content);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with(";==========\n"));
+}
+
+#[test]
+fn keeps_the_encoding_of_the_file() {
+    // "Bäckerei" and "Café" in windows-1252
+    let content = b"2021-02-01 * \"B\xe4ckerei\"\n2021-01-01 * \"Caf\xe9\"\n";
+    let in_path = env::temp_dir().join("beancount-sort_encoding_in.beancount");
+    let out_path = env::temp_dir().join("beancount-sort_encoding_out.beancount");
+    fs::write(&in_path, content).unwrap();
+    assert!(find_entries(read_file(&in_path).unwrap(), &ParseOptions::default()).is_err());
+
+    let options = ParseOptions {
+        encoding: parse_encoding("windows-1252").unwrap(),
+        ..Default::default()
+    };
+    let mut ledger_file = find_entries(read_file(&in_path).unwrap(), &options).unwrap();
+    assert!(ledger_file.entries[0].content.contains("Bäckerei"));
+    ledger_file.entries = sort_entries(ledger_file.entries, &SortOptions::default()).unwrap();
+    ledger_file
+        .write_ledger_file(open_output(&out_path).unwrap(), BlankLines::None)
+        .unwrap();
+    let sorted = fs::read(&out_path).unwrap();
+    fs::remove_file(&in_path).unwrap();
+    fs::remove_file(&out_path).unwrap();
+    let position = |needle: &[u8]| sorted.windows(needle.len()).position(|w| w == needle);
+    assert!(position(b"\"Caf\xe9\"\n").unwrap() < position(b"\"B\xe4ckerei\"\n").unwrap());
+
+    assert_eq!(parse_encoding("UTF-8").unwrap(), None);
+    assert!(parse_encoding("klingon").is_err());
+    assert!(parse_encoding("utf-16le").is_err());
+}
+
+#[test]
+fn asserts_that_the_written_file_is_sorted() {
+    let in_path = write_temp_file("assert_sorted_in.beancount", UNSORTED);
+    let out_path = env::temp_dir().join("beancount-sort_assert_sorted_out.beancount");
+    let output = Command::new(env!("CARGO_BIN_EXE_beancount-sort"))
+        .args(["--no-backup", "--assert-sorted", "--spaces", "--file"])
+        .arg(&in_path)
+        .arg("--out")
+        .arg(&out_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    // entries out of the date range are appended unsorted and stay there when it is sorted again
+    let output = Command::new(env!("CARGO_BIN_EXE_beancount-sort"))
+        .args(["--no-backup", "--assert-sorted", "--keep-out-of-range"])
+        .args(["--date-from", "2021-01-15", "--file"])
+        .arg(&in_path)
+        .arg("--out")
+        .arg(&out_path)
+        .output()
+        .unwrap();
+    fs::remove_file(&in_path).unwrap();
+    fs::remove_file(&out_path).unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let output = run_with_stdin(&["--file", "-", "--out", "-", "--assert-sorted"], UNSORTED);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn splits_transactions_by_year() {
+    let content = "\
+2021-01-01 open Assets:Giro   EUR
+2022-03-01 * \"Bakery\"
+    Assets:Giro   -3 EUR
+2021-05-01 * \"Bakery\"
+    Assets:Giro   -2 EUR
+2022-01-02 price GME   20 EUR
+";
+    let dir = env::temp_dir().join("beancount-sort_split_by_year");
+    fs::create_dir_all(&dir).unwrap();
+    let main_path = dir.join("main.beancount");
+    let in_path = write_temp_file("split_by_year.beancount", content);
+    let mut ledger_file =
+        find_entries(read_file(&in_path).unwrap(), &ParseOptions::default()).unwrap();
+    fs::remove_file(&in_path).unwrap();
+    ledger_file.entries = sort_entries(ledger_file.entries, &SortOptions::default()).unwrap();
+    let paths = ledger_file
+        .write_split_by_year(open_output(&main_path).unwrap(), &dir, BlankLines::None)
+        .unwrap();
+    assert_eq!(
+        paths,
+        [dir.join("2021.beancount"), dir.join("2022.beancount")]
+    );
+    let main = fs::read_to_string(&main_path).unwrap();
+    let year_2021 = fs::read_to_string(&paths[0]).unwrap();
+    let year_2022 = fs::read_to_string(&paths[1]).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let deco = DECO.repeat(NDECO);
+    assert!(main.contains("2021-01-01 open Assets:Giro"));
+    assert!(!main.contains("Bakery"));
+    assert!(!main.contains(&format!(";{}Transactions{}", deco, deco)));
+    assert!(main.contains(&format!(";{}Accounts{}", deco, deco)));
+    let include_2021 = main.find("include \"").unwrap();
+    assert!(main[include_2021..].contains(&format!(
+        "include \"{}\"\ninclude \"{}\"\n",
+        paths[0].display(),
+        paths[1].display()
+    )));
+    assert_eq!(
+        year_2021,
+        format!(
+            "{}\n2021-05-01 * \"Bakery\"\n    Assets:Giro   -2 EUR\n",
+            SectionDeco::default().heading("Transactions")
+        )
+    );
+    assert!(year_2022.contains(&format!(
+        "{}\n2022-01-02 price",
+        SectionDeco::default().heading("Prices")
+    )));
+    assert!(year_2022.contains("2022-03-01 * \"Bakery\""));
+}